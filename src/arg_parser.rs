@@ -1,35 +1,66 @@
 //! Command-line argument parser
 
 use crate::{
-    config::rllvm_config,
-    constants::{arg_exact_match_map, arg_patterns},
+    config::{FlagRole, FlagRule, RLLVMConfig},
+    constants::{
+        COMPILER_ARGS_INFO_SCHEMA_VERSION, SINGLE_COMPILE_INCOMPATIBLE_FLAG_PREFIXES,
+        SOURCE_FILE_LANGUAGES, arg_exact_match_map, arg_patterns,
+    },
     error::Error,
     utils::*,
 };
 use regex::Regex;
-use std::{path::PathBuf, sync::OnceLock};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::path::{Path, PathBuf};
 
 /// Compile mode
-#[derive(Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
 pub enum CompileMode {
     /// Compiling mode
     Compiling,
     /// Linking mode
     Linking,
     /// Link Time Optimization mode
+    #[serde(rename = "lto")]
     LTO,
     /// Bitcode Generation mode
     BitcodeGeneration,
+    /// Bitcode Lowering mode, i.e., generating the object file from bitcode
+    BitcodeLowering,
 }
 
 /// Compiler argument information
-#[derive(Debug, Default)]
+///
+/// It is serialized into a versioned schema (see
+/// [`COMPILER_ARGS_INFO_SCHEMA_VERSION`]), which also includes the derived
+/// `mode` and `bitcode_generation_skip_reason` regardless of the
+/// configuration. The derived fields are ignored when deserializing, and
+/// missing fields take their default values. Parsing states, e.g., where the
+/// input files are in `input_args`, are not part of the schema, and are
+/// restored when deserializing.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(remote = "Self", default)]
 pub struct CompilerArgsInfo {
     input_args: Vec<String>,
     input_files: Vec<String>,
+    #[serde(skip)]
+    input_file_indices: Vec<usize>,
+    /// Languages given by `-x` for the input files, if any
+    input_file_languages: Vec<Option<String>>,
+    #[serde(skip)]
+    language_flag_indices: Vec<usize>,
+    /// The language given by the last `-x`, which applies to the following
+    /// input files
+    #[serde(skip)]
+    language: Option<String>,
     object_files: Vec<String>,
     output_filename: String,
     compile_args: Vec<String>,
+    /// Dependency-generation flags (e.g., `-MD -MF foo.d`), which are kept
+    /// out of `compile_args`, so that only the user's compile writes
+    /// dependency files
+    dependency_args: Vec<String>,
     link_args: Vec<String>,
     forbidden_flags: Vec<String>,
     is_verbose: bool,
@@ -37,10 +68,143 @@ pub struct CompilerArgsInfo {
     is_preprocess_only: bool,
     is_assemble_only: bool,
     is_assembly: bool,
+    is_header: bool,
+    is_offloading: bool,
     is_compile_only: bool,
     is_emit_llvm: bool,
     is_lto: bool,
     is_print_only: bool,
+    /// Whether a user-defined flag rule skips the bitcode generation
+    is_skip_bitcode_flag_given: bool,
+    /// Flags that no rule recognizes
+    unknown_flags: Vec<String>,
+}
+
+/// Serialized compiler argument information, with the schema version and
+/// derived fields
+#[derive(Serialize, Deserialize)]
+struct CompilerArgsInfoDocument<T> {
+    schema_version: u32,
+    #[serde(skip_deserializing)]
+    mode: Option<CompileMode>,
+    #[serde(skip_deserializing)]
+    bitcode_generation_skip_reason: Option<&'static str>,
+    #[serde(flatten)]
+    args_info: T,
+}
+
+/// Serialize the fields of [`CompilerArgsInfo`]
+struct CompilerArgsInfoFields<'a>(&'a CompilerArgsInfo);
+
+impl Serialize for CompilerArgsInfoFields<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        CompilerArgsInfo::serialize(self.0, serializer)
+    }
+}
+
+/// Deserialize the fields of [`CompilerArgsInfo`]
+struct OwnedCompilerArgsInfoFields(CompilerArgsInfo);
+
+impl<'de> Deserialize<'de> for OwnedCompilerArgsInfoFields {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        CompilerArgsInfo::deserialize(deserializer).map(Self)
+    }
+}
+
+impl Serialize for CompilerArgsInfo {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        CompilerArgsInfoDocument {
+            schema_version: COMPILER_ARGS_INFO_SCHEMA_VERSION,
+            mode: Some(self.mode()),
+            bitcode_generation_skip_reason: self.arguments_skip_reason(),
+            args_info: CompilerArgsInfoFields(self),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for CompilerArgsInfo {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let document =
+            CompilerArgsInfoDocument::<OwnedCompilerArgsInfoFields>::deserialize(deserializer)?;
+        if document.schema_version != COMPILER_ARGS_INFO_SCHEMA_VERSION {
+            return Err(serde::de::Error::custom(format!(
+                "unsupported schema version: {} (expected {})",
+                document.schema_version, COMPILER_ARGS_INFO_SCHEMA_VERSION
+            )));
+        }
+
+        let mut args_info = document.args_info.0;
+        args_info
+            .restore_parsing_states()
+            .map_err(serde::de::Error::custom)?;
+
+        Ok(args_info)
+    }
+}
+
+/// Obtain the language of the source file from its extension, as named by
+/// `-x`, e.g., `c++` for `foo.cppm`
+pub fn source_file_language(filepath: &str) -> Option<&'static str> {
+    let (_, extension) = filepath.rsplit_once('.')?;
+    SOURCE_FILE_LANGUAGES
+        .iter()
+        .find(|(name, _)| *name == extension)
+        .map(|(_, language)| *language)
+}
+
+/// Compile the user-defined flag rules into patterns, which are matched ahead
+/// of the built-in ones
+pub fn flag_rule_patterns(rules: &[FlagRule]) -> Result<Vec<ArgPatternInfo<String>>, Error> {
+    let mut patterns = vec![];
+    for rule in rules {
+        let pattern = match (rule.flag(), rule.pattern()) {
+            (Some(flag), None) => format!("^{}$", regex::escape(flag)),
+            (None, Some(pattern)) => pattern.to_string(),
+            _ => {
+                return Err(Error::InvalidConfig(format!(
+                    "Exactly one of `flag` and `pattern` is required in a flag rule: {:?}",
+                    rule
+                )));
+            }
+        };
+        let pattern = Regex::new(&pattern).map_err(|err| {
+            Error::InvalidConfig(format!("Invalid pattern in a flag rule: {}", err))
+        })?;
+
+        let handler: CallbackFn<String> = match rule.role() {
+            FlagRole::Compile => CompilerArgsInfo::compile_nary,
+            FlagRole::Link => CompilerArgsInfo::link_nary,
+            FlagRole::Both => CompilerArgsInfo::compile_link_nary,
+            FlagRole::SkipBitcode => CompilerArgsInfo::skip_bitcode,
+            // Parameters of forbidden flags cannot be removed by value
+            FlagRole::Forbidden if rule.arity() > 0 => {
+                return Err(Error::InvalidConfig(format!(
+                    "Forbidden flags cannot take parameters: {:?}",
+                    rule
+                )));
+            }
+            FlagRole::Forbidden => CompilerArgsInfo::warning_link_unary,
+        };
+        patterns.push(ArgPatternInfo {
+            pattern,
+            arg_info: ArgInfo::new(rule.arity(), handler),
+        });
+    }
+
+    Ok(patterns)
 }
 
 pub type CallbackFn<S> = for<'a> fn(&'a mut CompilerArgsInfo, S, &[S]) -> &'a mut CompilerArgsInfo;
@@ -88,17 +252,38 @@ impl CompilerArgsInfo {
         S: AsRef<str>,
     {
         self.input_files.push(flag.as_ref().to_string());
-
-        // Assembly files
-        static RE: OnceLock<Regex> = OnceLock::new();
-        let re = RE.get_or_init(|| Regex::new(r"\.(s|S)$").unwrap());
-        if re.is_match(flag.as_ref()) {
-            self.is_assembly = true;
+        self.input_file_languages.push(self.language.clone());
+
+        let language = self
+            .language
+            .as_deref()
+            .or_else(|| source_file_language(flag.as_ref()));
+        match language {
+            // Assembly files
+            Some(language) if language.starts_with("assembler") => self.is_assembly = true,
+            // Header files, which are precompiled
+            Some(language) if language.ends_with("-header") => self.is_header = true,
+            // Files with device code
+            Some("cuda" | "hip") => self.is_offloading = true,
+            _ => {}
         }
 
         self
     }
 
+    pub fn language<S>(&mut self, flag: S, args: &[S]) -> &'_ mut Self
+    where
+        S: AsRef<str>,
+    {
+        let language = match args.first() {
+            Some(language) => language.as_ref(),
+            // The joined form, e.g., `-xc++`
+            None => &flag.as_ref()["-x".len()..],
+        };
+        self.language = (language != "none").then(|| language.to_string());
+        self
+    }
+
     pub fn output_file<S>(&mut self, _flag: S, args: &[S]) -> &'_ mut Self
     where
         S: AsRef<str>,
@@ -142,7 +327,7 @@ impl CompilerArgsInfo {
         S: AsRef<str>,
     {
         self.is_dependency_only = true;
-        self.compile_args.push(flag.as_ref().to_string());
+        self.dependency_args.push(flag.as_ref().to_string());
         self
     }
 
@@ -233,9 +418,18 @@ impl CompilerArgsInfo {
     where
         S: AsRef<str>,
     {
-        self.compile_args.push(flag.as_ref().to_string());
-        self.compile_args.push(args[0].as_ref().to_string());
-        self.is_dependency_only = true;
+        self.dependency_args.push(flag.as_ref().to_string());
+        self.dependency_args.push(args[0].as_ref().to_string());
+        self
+    }
+
+    pub fn dependency_unary<S>(&mut self, flag: S, _args: &[S]) -> &'_ mut Self
+    where
+        S: AsRef<str>,
+    {
+        // NOTE: dependency files are written as a side effect of the
+        // compilation, unlike `-M` and `-MM`, which replace it
+        self.dependency_args.push(flag.as_ref().to_string());
         self
     }
 
@@ -281,7 +475,56 @@ impl CompilerArgsInfo {
         self
     }
 
-    fn consume_params<S>(&mut self, i: usize, arg: S, arg_info: &ArgInfo<S>, args: &[S]) -> usize
+    pub fn compile_nary<S>(&mut self, flag: S, args: &[S]) -> &'_ mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.compile_args.push(flag.as_ref().to_string());
+        self.compile_args
+            .extend(args.iter().map(|arg| arg.as_ref().to_string()));
+        self
+    }
+
+    pub fn link_nary<S>(&mut self, flag: S, args: &[S]) -> &'_ mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.link_args.push(flag.as_ref().to_string());
+        self.link_args
+            .extend(args.iter().map(|arg| arg.as_ref().to_string()));
+        self
+    }
+
+    pub fn compile_link_nary<S>(&mut self, flag: S, args: &[S]) -> &'_ mut Self
+    where
+        S: AsRef<str>,
+    {
+        let args: Vec<String> = args.iter().map(|arg| arg.as_ref().to_string()).collect();
+
+        self.compile_args.push(flag.as_ref().to_string());
+        self.compile_args.extend(args.iter().cloned());
+
+        self.link_args.push(flag.as_ref().to_string());
+        self.link_args.extend(args);
+
+        self
+    }
+
+    pub fn skip_bitcode<S>(&mut self, _flag: S, _args: &[S]) -> &'_ mut Self
+    where
+        S: AsRef<str>,
+    {
+        self.is_skip_bitcode_flag_given = true;
+        self
+    }
+
+    fn consume_params<S>(
+        &mut self,
+        i: usize,
+        arg: S,
+        arg_info: &ArgInfo<S>,
+        args: &[S],
+    ) -> Result<usize, Error>
     where
         S: AsRef<str>,
     {
@@ -289,17 +532,39 @@ impl CompilerArgsInfo {
         // Exclude the current argument
         let param_start = i + 1;
         let param_end = param_start + arg_info.arity;
+        if param_end > args.len() {
+            return Err(Error::InvalidArguments(format!(
+                "Missing parameters of `{}`: expected {}",
+                arg.as_ref(),
+                arg_info.arity
+            )));
+        }
         let params = &args[param_start..param_end];
         handler(self, arg, params);
 
-        arg_info.arity
+        Ok(arg_info.arity)
     }
 
+    /// Parse the compiler arguments, where response files (`@file`) are
+    /// expanded before classification
     pub fn parse_args<S>(&mut self, args: &[S]) -> Result<&'_ mut Self, Error>
     where
         S: AsRef<str>,
     {
-        let args: Vec<String> = args.iter().map(|x| x.as_ref().to_string()).collect();
+        self.parse_args_with_rules(args, &[])
+    }
+
+    /// Parse the compiler arguments, where the given patterns (see
+    /// [`flag_rule_patterns`]) are matched ahead of the built-in ones
+    pub fn parse_args_with_rules<S>(
+        &mut self,
+        args: &[S],
+        flag_rules: &[ArgPatternInfo<String>],
+    ) -> Result<&'_ mut Self, Error>
+    where
+        S: AsRef<str>,
+    {
+        let args = expand_response_files(args)?;
         self.input_args = args.clone();
 
         let mut i = 0;
@@ -307,11 +572,25 @@ impl CompilerArgsInfo {
             let arg = &args[i];
             // Consume the current argument, by default
             let mut offset = 1;
+            let num_input_files = self.input_files.len();
+            if arg.starts_with("-x") {
+                self.language_flag_indices.push(i);
+            }
 
-            // Try to match the flag exactly
-            if let Some(arg_info) = arg_exact_match_map().get(arg.as_str()) {
+            // Try to match user-defined rules, and then the flag exactly
+            if let Some(flag_rule) = flag_rules
+                .iter()
+                .find(|flag_rule| flag_rule.pattern.is_match(arg))
+            {
+                // Consume more parameters
+                offset += self.consume_params(i, arg.to_string(), &flag_rule.arg_info, &args)?;
+            } else if let Some(arg_info) = arg_exact_match_map().get(arg.as_str()) {
                 // Consume more parameters
-                offset += self.consume_params(i, arg.to_string(), arg_info, &args);
+                offset += self.consume_params(i, arg.to_string(), arg_info, &args)?;
+            } else if self.language.is_some() && !arg.starts_with('-') {
+                // Any file following `-x` is an input file in that language,
+                // regardless of its extension
+                self.input_file(arg, &[]);
             } else if arg == "-Wl,--start-group" {
                 // Need to handle the N-ary grouping flag
                 if let Some(group_end) = args[i..].iter().position(|x| x == "-Wl,--end-group") {
@@ -336,7 +615,7 @@ impl CompilerArgsInfo {
                     let arg_info = &arg_pattern.arg_info;
                     if pattern.is_match(arg.as_str()) {
                         // Consume more parameters
-                        offset += self.consume_params(i, arg.to_string(), arg_info, &args);
+                        offset += self.consume_params(i, arg.to_string(), arg_info, &args)?;
 
                         matched = true;
                         break;
@@ -347,12 +626,21 @@ impl CompilerArgsInfo {
                         CompilerArgsInfo::object_file
                     } else {
                         // Failed to recognize the compiler flag
+                        if arg.starts_with('-') {
+                            self.unknown_flags.push(arg.to_string());
+                        }
                         CompilerArgsInfo::compile_unary
                     };
                     handler(self, arg, &[]);
                 }
             }
 
+            if self.input_files.len() > num_input_files {
+                // Remember where the input file is, to link its object file
+                // at the same position
+                self.input_file_indices.push(i);
+            }
+
             i += offset;
         }
 
@@ -361,15 +649,65 @@ impl CompilerArgsInfo {
 }
 
 impl CompilerArgsInfo {
-    pub fn input_args(&self) -> &Vec<String> {
+    /// Restore the parsing states that are not serialized, i.e., where the
+    /// input files and `-x` flags are in `input_args`, and check that the
+    /// fields are consistent
+    fn restore_parsing_states(&mut self) -> Result<(), Error> {
+        // Input files without languages are in their default languages
+        if self.input_file_languages.len() > self.input_files.len() {
+            return Err(Error::InvalidArguments(format!(
+                "Expected at most {} input file languages, but got {}",
+                self.input_files.len(),
+                self.input_file_languages.len()
+            )));
+        }
+        self.input_file_languages
+            .resize(self.input_files.len(), None);
+
+        self.input_file_indices.clear();
+        self.language_flag_indices.clear();
+        let mut input_files = self.input_files.iter().peekable();
+        let mut i = 0;
+        while i < self.input_args.len() {
+            let arg = &self.input_args[i];
+            if input_files
+                .next_if(|input_file| *input_file == arg)
+                .is_some()
+            {
+                self.input_file_indices.push(i);
+            } else if arg.starts_with("-x") {
+                self.language_flag_indices.push(i);
+            }
+            // Skip parameters, which are neither input files nor flags
+            if let Some(arg_info) = arg_exact_match_map().get(arg.as_str()) {
+                i += arg_info.arity;
+            }
+            i += 1;
+        }
+        if let Some(input_file) = input_files.next() {
+            return Err(Error::InvalidArguments(format!(
+                "The input file is not in the input arguments: {}",
+                input_file
+            )));
+        }
+
+        Ok(())
+    }
+
+    pub fn input_args(&self) -> &[String] {
         self.input_args.as_ref()
     }
 
-    pub fn input_files(&self) -> &Vec<String> {
+    pub fn input_files(&self) -> &[String] {
         self.input_files.as_ref()
     }
 
-    pub fn object_files(&self) -> &Vec<String> {
+    /// Obtain the languages given by `-x` for the input files, if any
+    pub fn input_file_languages(&self) -> &[Option<String>] {
+        &self.input_file_languages
+    }
+
+    pub fn object_files(&self) -> &[String] {
         self.object_files.as_ref()
     }
 
@@ -377,15 +715,19 @@ impl CompilerArgsInfo {
         self.output_filename.as_ref()
     }
 
-    pub fn compile_args(&self) -> &Vec<String> {
+    pub fn compile_args(&self) -> &[String] {
         self.compile_args.as_ref()
     }
 
-    pub fn link_args(&self) -> &Vec<String> {
+    pub fn dependency_args(&self) -> &[String] {
+        self.dependency_args.as_ref()
+    }
+
+    pub fn link_args(&self) -> &[String] {
         self.link_args.as_ref()
     }
 
-    pub fn forbidden_flags(&self) -> &Vec<String> {
+    pub fn forbidden_flags(&self) -> &[String] {
         self.forbidden_flags.as_ref()
     }
 
@@ -409,6 +751,14 @@ impl CompilerArgsInfo {
         self.is_assembly
     }
 
+    pub fn is_header(&self) -> bool {
+        self.is_header
+    }
+
+    pub fn is_offloading(&self) -> bool {
+        self.is_offloading
+    }
+
     pub fn is_compile_only(&self) -> bool {
         self.is_compile_only
     }
@@ -425,15 +775,41 @@ impl CompilerArgsInfo {
         self.is_print_only
     }
 
-    pub fn is_bitcode_generation_skipped(&self) -> bool {
-        let mut is_skipped = false;
-        let mut message = "no reason";
+    pub fn is_skip_bitcode_flag_given(&self) -> bool {
+        self.is_skip_bitcode_flag_given
+    }
+
+    /// Obtain the flags that no rule recognizes, which are passed to compile
+    /// commands
+    pub fn unknown_flags(&self) -> &[String] {
+        &self.unknown_flags
+    }
+
+    pub fn is_bitcode_generation_skipped(&self, config: &RLLVMConfig) -> bool {
+        match self.bitcode_generation_skip_reason(config) {
+            Some(reason) => {
+                log::warn!("Skip bitcode generation: {}", reason);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Obtain the reason why the bitcode generation is skipped, if it is
+    pub fn bitcode_generation_skip_reason(&self, config: &RLLVMConfig) -> Option<&'static str> {
+        self.arguments_skip_reason().or_else(|| {
+            config
+                .is_configure_only()
+                .then_some("we are in configure-only mode")
+        })
+    }
+
+    /// Obtain the reason why the arguments skip the bitcode generation,
+    /// regardless of the configuration
+    fn arguments_skip_reason(&self) -> Option<&'static str> {
+        let mut skip_reason = None;
 
         let conditions = [
-            (
-                rllvm_config().is_configure_only(),
-                "we are in configure-only mode",
-            ),
             (
                 self.input_files.is_empty(),
                 "the list of input files is empty",
@@ -450,6 +826,14 @@ impl CompilerArgsInfo {
                 self.is_assembly,
                 "the input file(s) are written in assembly",
             ),
+            (
+                self.is_header,
+                "the input file(s) are headers, which are precompiled instead of compiled",
+            ),
+            (
+                self.is_offloading,
+                "the input file(s) contain device code, which is compiled into multiple modules",
+            ),
             (
                 self.is_assemble_only,
                 "we are only assembling, so cannot embed the path of the bitcode",
@@ -463,20 +847,37 @@ impl CompilerArgsInfo {
                 self.is_print_only,
                 "we are in print-only mode, so cannot embed the path of the bitcode",
             ),
+            (
+                self.is_skip_bitcode_flag_given,
+                "a flag rule skips the bitcode generation",
+            ),
         ];
 
         for (condition, reason) in conditions {
             if condition {
-                is_skipped = true;
-                message = reason;
+                skip_reason = Some(reason);
             }
         }
 
-        if is_skipped {
-            log::warn!("Skip bitcode generation: {}", message);
-        }
+        skip_reason
+    }
 
-        is_skipped
+    /// Obtain the first compile flag that prevents lowering the object file
+    /// from the generated bitcode, i.e., the single-compile pipeline
+    ///
+    /// Every dependency-generation flag does, as only the user's compile
+    /// writes dependency files.
+    pub fn single_compile_incompatible_flag(&self) -> Option<&str> {
+        self.dependency_args
+            .first()
+            .or_else(|| {
+                self.compile_args.iter().find(|arg| {
+                    SINGLE_COMPILE_INCOMPATIBLE_FLAG_PREFIXES
+                        .iter()
+                        .any(|prefix| arg.starts_with(prefix))
+                })
+            })
+            .map(String::as_str)
     }
 
     pub fn mode(&self) -> CompileMode {
@@ -491,31 +892,131 @@ impl CompilerArgsInfo {
         mode
     }
 
-    pub fn artifact_filepaths(&self) -> Result<Vec<(PathBuf, PathBuf, PathBuf)>, Error> {
+    /// Calculate the artifact hash of one source file, over its remapped
+    /// filepath, the remapped output filepath and the effective compile flags
+    fn artifact_hash(&self, src_filepath: &Path, config: &RLLVMConfig) -> Result<u64, Error> {
+        let path_prefix_maps = config.bitcode_path_prefix_maps();
+
+        let src_filepath = apply_path_prefix_maps(src_filepath, &path_prefix_maps);
+        let output_filepath = if self.output_filename.is_empty() {
+            None
+        } else {
+            Some(apply_path_prefix_maps(
+                std::path::absolute(&self.output_filename)?,
+                &path_prefix_maps,
+            ))
+        };
+        let compile_args: Vec<_> = self
+            .compile_args
+            .iter()
+            .chain(config.bitcode_generation_flags().into_iter().flatten())
+            .map(|arg| apply_path_prefix_maps_to_arg(arg, &path_prefix_maps))
+            .collect();
+
+        Ok(calculate_artifact_hash(
+            &src_filepath,
+            output_filepath.as_ref(),
+            &compile_args,
+        ))
+    }
+
+    /// Obtain the object file that the compiler writes for the source file in
+    /// compile-only mode, i.e., the output file, or `<stem>.o` in the current
+    /// working directory if no output file is given
+    pub fn compiled_object_filepath(&self, src_filepath: &Path) -> Result<Option<PathBuf>, Error> {
+        if !self.is_compile_only {
+            return Ok(None);
+        }
+
+        if !self.output_filename.is_empty() {
+            if self.input_files.len() > 1 {
+                return Err(Error::InvalidArguments(format!(
+                    "Cannot specify the output file with multiple input files: {:?}",
+                    self.output_filename
+                )));
+            }
+            return Ok(Some(std::path::absolute(&self.output_filename)?));
+        }
+
+        let file_stem = src_filepath.file_stem().ok_or_else(|| {
+            Error::InvalidArguments(format!(
+                "Failed to obtain the file stem: {:?}",
+                src_filepath
+            ))
+        })?;
+        let mut object_file_name = file_stem.to_os_string();
+        object_file_name.push(".o");
+
+        Ok(Some(std::path::absolute(object_file_name)?))
+    }
+
+    /// Obtain the arguments to link the object files of the input files, where
+    /// every input file is replaced in place by its object file, so that the
+    /// order relative to link flags (e.g., `-l` and `-Wl,--start-group`) is
+    /// preserved
+    pub fn object_link_args<P>(&self, object_filepaths: &[P]) -> Result<Vec<String>, Error>
+    where
+        P: AsRef<Path>,
+    {
+        if object_filepaths.len() != self.input_file_indices.len() {
+            return Err(Error::InvalidArguments(format!(
+                "Expected {} object files, but got {}",
+                self.input_file_indices.len(),
+                object_filepaths.len()
+            )));
+        }
+
+        let mut args = vec![];
+        let mut object_filepaths = self
+            .input_file_indices
+            .iter()
+            .zip(object_filepaths)
+            .peekable();
+        let mut i = 0;
+        while i < self.input_args.len() {
+            if let Some((_, object_filepath)) = object_filepaths.next_if(|(index, _)| **index == i)
+            {
+                args.push(object_filepath.as_ref().to_string_lossy().into_owned());
+            } else if self.language_flag_indices.contains(&i) {
+                // The language of the input files does not apply to object
+                // files
+                if self.input_args[i] == "-x" {
+                    i += 1;
+                }
+            } else {
+                args.push(self.input_args[i].clone());
+            }
+            i += 1;
+        }
+
+        Ok(args)
+    }
+
+    pub fn artifact_filepaths(
+        &self,
+        config: &RLLVMConfig,
+    ) -> Result<Vec<(PathBuf, PathBuf, PathBuf)>, Error> {
         let mut artifacts = vec![];
         for src_file in &self.input_files {
             // Obtain the absolute filepath
             let src_filepath = PathBuf::from(src_file).canonicalize()?;
 
             // Derive filepaths of artifacts
-            let (object_filepath, mut bitcode_filepath) =
-                derive_object_and_bitcode_filepath(&src_filepath, self.is_compile_only)?;
+            let artifact_hash = self.artifact_hash(&src_filepath, config)?;
+            let compiled_object_filepath = self.compiled_object_filepath(&src_filepath)?;
+            let (object_filepath, mut bitcode_filepath) = derive_object_and_bitcode_filepath(
+                src_filepath.as_path(),
+                compiled_object_filepath.as_deref(),
+                artifact_hash,
+            )?;
 
             // Update the bitcode filepath, if the bitcode store path is provided
-            if let Some(bitcode_store_path) = rllvm_config().bitcode_store_path() {
+            if let Some(bitcode_store_path) = config.bitcode_store_path() {
                 if bitcode_store_path.exists() {
-                    // Obtain a new bitcode filename based on the hash of the source filepath
-                    if bitcode_filepath.file_name().is_some() {
-                        let src_filepath_hash = calculate_filepath_hash(&src_filepath);
-                        let bitcode_file_stem =
-                            bitcode_filepath.file_stem().unwrap().to_string_lossy();
-                        let bitcode_file_ext =
-                            bitcode_filepath.extension().unwrap().to_string_lossy();
-
-                        let new_bitcode_filename =
-                            format!("{bitcode_file_stem}_{src_filepath_hash}.{bitcode_file_ext}");
-
-                        bitcode_filepath = bitcode_store_path.join(new_bitcode_filename);
+                    // The bitcode filename is already unique, as it includes the
+                    // artifact hash
+                    if let Some(bitcode_filename) = bitcode_filepath.file_name() {
+                        bitcode_filepath = bitcode_store_path.join(bitcode_filename);
                     } else {
                         log::warn!("Cannot obtain the bitcode filename: {:?}", bitcode_filepath);
                     }
@@ -535,7 +1036,9 @@ impl CompilerArgsInfo {
 
 #[cfg(test)]
 mod tests {
-    use super::CompilerArgsInfo;
+    use super::{CompileMode, CompilerArgsInfo, flag_rule_patterns};
+    use crate::config::{FlagRule, RLLVMConfig};
+    use crate::constants::COMPILER_ARGS_INFO_SCHEMA_VERSION;
 
     fn test_parsing<F>(input: &str, check_func: F)
     where
@@ -561,6 +1064,299 @@ mod tests {
         test_parsing_lto_internal(input);
     }
 
+    #[test]
+    fn test_object_link_args() {
+        let input = r#"-O2 -x c a.c -Xlinker -x -lfoo b.c -Wl,--start-group -lbar -lbaz -Wl,--end-group -o prog"#;
+        test_parsing(input, |args| {
+            let object_filepaths = [".a.o", ".b.o"];
+            args.object_link_args(&object_filepaths)
+                .is_ok_and(|link_args| {
+                    link_args
+                        == [
+                            "-O2",
+                            ".a.o",
+                            "-Xlinker",
+                            "-x",
+                            "-lfoo",
+                            ".b.o",
+                            "-Wl,--start-group",
+                            "-lbar",
+                            "-lbaz",
+                            "-Wl,--end-group",
+                            "-o",
+                            "prog",
+                        ]
+                })
+                && args.object_link_args(&object_filepaths[..1]).is_err()
+        });
+    }
+
+    #[test]
+    fn test_single_compile_incompatible_flag() {
+        test_parsing("-c -O2 -fPIC -o foo.o foo.c", |args| {
+            args.single_compile_incompatible_flag().is_none()
+        });
+        test_parsing("-c -O2 -MD -MF foo.d -o foo.o foo.c", |args| {
+            args.single_compile_incompatible_flag() == Some("-MD")
+        });
+        test_parsing("-c -gsplit-dwarf -o foo.o foo.c", |args| {
+            args.single_compile_incompatible_flag() == Some("-gsplit-dwarf")
+        });
+        test_parsing("-c -O2 -Wp,-MMD,.foo.o.d -o foo.o foo.c", |args| {
+            args.single_compile_incompatible_flag() == Some("-Wp,-MMD,.foo.o.d")
+        });
+    }
+
+    #[test]
+    fn test_parsing_dependency_args() {
+        let input = r#"-c -O2 -MD -MF foo.d -MT foo.o -MQfoo.o -MJfoo.json -Wp,-MMD,.foo.o.d -Wall -o foo.o foo.c"#;
+        test_parsing(input, |args| {
+            args.compile_args() == ["-O2", "-Wall"]
+                && args.dependency_args()
+                    == [
+                        "-MD",
+                        "-MF",
+                        "foo.d",
+                        "-MT",
+                        "foo.o",
+                        "-MQfoo.o",
+                        "-MJfoo.json",
+                        "-Wp,-MMD,.foo.o.d",
+                    ]
+                && args.input_args().len() == 14
+                && !args.is_dependency_only()
+        });
+        test_parsing("-MM -MG -MP -MF foo.d foo.c", |args| {
+            args.is_dependency_only()
+                && args.dependency_args() == ["-MM", "-MG", "-MP", "-MF", "foo.d"]
+        });
+        test_parsing("-Wp,-M foo.c", |args| args.is_dependency_only());
+        test_parsing("-MD -Wp,-MD,foo.d foo.c", |args| !args.is_dependency_only());
+    }
+
+    #[test]
+    fn test_parsing_languages() {
+        test_parsing(
+            "-c foo.m bar.mm baz.c++ qux.cppm quux.ii corge.ll FOO.CPP",
+            |args| args.input_files().len() == 7 && !args.is_assembly() && !args.is_header(),
+        );
+        test_parsing(
+            "-c -x c foo.inc -xc++ bar.inc -x none baz.c -o out.o",
+            |args| {
+                args.input_files() == ["foo.inc", "bar.inc", "baz.c"]
+                    && args.input_file_languages()
+                        == [Some("c".to_string()), Some("c++".to_string()), None]
+                    && args.compile_args().is_empty()
+            },
+        );
+        test_parsing("-x assembler-with-cpp -c foo.inc", |args| {
+            args.is_assembly()
+        });
+        test_parsing("-x c++-header foo.hpp", |args| args.is_header());
+        test_parsing("-c foo.cu", |args| args.is_offloading());
+        test_parsing("-xc a.inc -lm -o prog", |args| {
+            args.object_link_args(&[".a.o"])
+                .is_ok_and(|link_args| link_args == [".a.o", "-lm", "-o", "prog"])
+        });
+        assert_eq!(super::source_file_language("foo.c++"), Some("c++"));
+        assert_eq!(super::source_file_language("foo.ll"), Some("ir"));
+        assert_eq!(super::source_file_language("foo"), None);
+    }
+
+    #[test]
+    fn test_serialization() {
+        let mut args = CompilerArgsInfo::default();
+        args.parse_args(&["-O2", "-x", "c", "foo.inc", "-lm", "-o", "foo"])
+            .expect("Failed to parse arguments");
+
+        let value = serde_json::to_value(&args).expect("Failed to serialize");
+        assert_eq!(value["schema_version"], COMPILER_ARGS_INFO_SCHEMA_VERSION);
+        assert_eq!(value["mode"], "compiling");
+        assert!(value["bitcode_generation_skip_reason"].is_null());
+        assert_eq!(value["input_files"], serde_json::json!(["foo.inc"]));
+        assert_eq!(value["input_file_languages"], serde_json::json!(["c"]));
+        assert_eq!(value["compile_args"], serde_json::json!(["-O2"]));
+        assert!(value.get("language").is_none());
+
+        // Round trip
+        let deserialized: CompilerArgsInfo =
+            serde_json::from_value(value.clone()).expect("Failed to deserialize");
+        assert_eq!(deserialized.input_args(), args.input_args());
+        assert_eq!(deserialized.link_args(), args.link_args());
+        assert_eq!(
+            deserialized.object_link_args(&[".foo.o"]).unwrap(),
+            args.object_link_args(&[".foo.o"]).unwrap()
+        );
+
+        // The skip reason does not depend on the configuration
+        let mut args = CompilerArgsInfo::default();
+        args.parse_args(&["-E", "foo.c"]).unwrap();
+        let value = serde_json::to_value(&args).expect("Failed to serialize");
+        assert_eq!(
+            value["bitcode_generation_skip_reason"],
+            "we are only preprocessing"
+        );
+
+        // The schema only includes public fields
+        let value = serde_json::to_value(CompilerArgsInfo::default()).unwrap();
+        let mut keys: Vec<_> = value.as_object().unwrap().keys().cloned().collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                "bitcode_generation_skip_reason",
+                "compile_args",
+                "dependency_args",
+                "forbidden_flags",
+                "input_args",
+                "input_file_languages",
+                "input_files",
+                "is_assemble_only",
+                "is_assembly",
+                "is_compile_only",
+                "is_dependency_only",
+                "is_emit_llvm",
+                "is_header",
+                "is_lto",
+                "is_offloading",
+                "is_preprocess_only",
+                "is_print_only",
+                "is_skip_bitcode_flag_given",
+                "is_verbose",
+                "link_args",
+                "mode",
+                "object_files",
+                "output_filename",
+                "schema_version",
+                "unknown_flags",
+            ]
+        );
+
+        // Missing fields take their default values, and the positions of
+        // input files and `-x` flags are restored
+        let deserialized: CompilerArgsInfo = serde_json::from_str(
+            r#"{
+                "schema_version": 1,
+                "mode": "linking",
+                "input_args": ["-o", "bar.c", "-x", "c", "foo.inc", "-lm"],
+                "input_files": ["foo.inc"]
+            }"#,
+        )
+        .expect("Failed to deserialize");
+        assert_eq!(deserialized.input_files(), ["foo.inc"]);
+        assert_eq!(deserialized.input_file_languages(), [None]);
+        assert!(matches!(deserialized.mode(), CompileMode::Compiling));
+        assert_eq!(
+            deserialized.object_link_args(&[".foo.o"]).unwrap(),
+            ["-o", "bar.c", ".foo.o", "-lm"]
+        );
+
+        // Inconsistent fields and unsupported versions are rejected
+        for json in [
+            r#"{"schema_version": 1, "input_files": ["foo.c"]}"#,
+            r#"{"schema_version": 1, "input_args": ["foo.c"], "input_file_languages": ["c", "c"], "input_files": ["foo.c"]}"#,
+            r#"{"schema_version": 2}"#,
+        ] {
+            assert!(serde_json::from_str::<CompilerArgsInfo>(json).is_err());
+        }
+        assert!(serde_json::from_str::<CompilerArgsInfo>(r#"{}"#).is_err());
+    }
+
+    #[test]
+    fn test_flag_rules() {
+        let rules: toml::Table = r#"
+flag_rules = [
+    { flag = "-mfoo", arity = 1, role = "compile" },
+    { pattern = "^-Wl,--bar=.*$", role = "forbidden" },
+    { flag = "-fsyntax-check", role = "skip-bitcode" },
+    { pattern = "^-mcpu=.+$", role = "both" },
+]
+"#
+        .parse()
+        .unwrap();
+        let rules: Vec<FlagRule> = rules["flag_rules"].clone().try_into().unwrap();
+        let patterns = flag_rule_patterns(&rules).expect("Failed to compile flag rules");
+
+        let mut args_info = CompilerArgsInfo::default();
+        args_info
+            .parse_args_with_rules(
+                &[
+                    "-c",
+                    "-mfoo",
+                    "x",
+                    "-Wl,--bar=1",
+                    "-mcpu=v2",
+                    "-mbaz",
+                    "-o",
+                    "a.o",
+                    "a.c",
+                ],
+                &patterns,
+            )
+            .expect("Failed to parse arguments");
+        assert_eq!(
+            args_info.compile_args(),
+            &["-mfoo", "x", "-mcpu=v2", "-mbaz"]
+        );
+        assert_eq!(args_info.link_args(), &["-mcpu=v2"]);
+        assert_eq!(args_info.forbidden_flags(), &["-Wl,--bar=1"]);
+        assert_eq!(args_info.unknown_flags(), &["-mbaz"]);
+
+        let mut args_info = CompilerArgsInfo::default();
+        args_info
+            .parse_args_with_rules(&["-c", "-fsyntax-check", "a.c"], &patterns)
+            .expect("Failed to parse arguments");
+        let config_filepath = std::path::Path::new("/tmp/rllvm_test_flag_rules.toml");
+        std::fs::write(
+            config_filepath,
+            r#"
+llvm_config_filepath = '/usr/bin/llvm-config'
+clang_filepath = '/usr/bin/clang'
+clangxx_filepath = '/usr/bin/clang++'
+llvm_ar_filepath = '/usr/bin/llvm-ar'
+llvm_link_filepath = '/usr/bin/llvm-link'
+llvm_objcopy_filepath = '/usr/bin/llvm-objcopy'
+"#,
+        )
+        .expect("Failed to write the config file");
+        let config = RLLVMConfig::try_load(config_filepath).expect("Failed to load the config");
+        assert_eq!(
+            args_info.bitcode_generation_skip_reason(&config),
+            Some("a flag rule skips the bitcode generation")
+        );
+
+        // The skip survives the serialization, where the rules are unknown
+        let value = serde_json::to_value(&args_info).expect("Failed to serialize");
+        let deserialized: CompilerArgsInfo =
+            serde_json::from_value(value).expect("Failed to deserialize");
+        assert!(deserialized.is_skip_bitcode_flag_given());
+        assert_eq!(
+            deserialized.bitcode_generation_skip_reason(&config),
+            Some("a flag rule skips the bitcode generation")
+        );
+        std::fs::remove_file(config_filepath).expect("Failed to delete the config file");
+
+        // Missing parameters
+        assert!(
+            CompilerArgsInfo::default()
+                .parse_args_with_rules(&["-c", "a.c", "-mfoo"], &patterns)
+                .is_err()
+        );
+
+        // Invalid rules
+        let invalid_rules = [
+            r#"{ role = "compile" }"#,
+            r#"{ flag = "-a", pattern = "^-a$", role = "compile" }"#,
+            r#"{ pattern = "(", role = "compile" }"#,
+            r#"{ flag = "-a", arity = 1, role = "forbidden" }"#,
+        ];
+        for rule in invalid_rules {
+            let rule: toml::Table = format!("rule = {}", rule).parse().unwrap();
+            let rule: FlagRule = rule["rule"].clone().try_into().unwrap();
+            assert!(flag_rule_patterns(&[rule]).is_err());
+        }
+    }
+
     fn test_parsing_link_args_internal(input: &str, expected: usize) {
         test_parsing(input, |args| args.link_args().len() == expected);
     }
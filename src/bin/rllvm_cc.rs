@@ -1,16 +1,26 @@
-use std::path::PathBuf;
+use std::{env, path::PathBuf, sync::Arc};
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use log::LevelFilter;
 use rllvm::{
     compiler_wrapper::{
         CompilerKind, CompilerWrapper, CompilerWrapperBuilder, llvm::ClangWrapperBuilder,
     },
-    config::rllvm_config,
+    config::{BitcodeEmbedding, BitcodeGenerationPolicy, RLLVMConfig, set_rllvm_config},
     error::Error,
+    utils::{PathPrefixMap, aggregate_unknown_flags, exit_with_error},
 };
 use simple_logger::SimpleLogger;
 
+/// Output format of the dry run
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ExplainFormat {
+    /// Human-readable text
+    Text,
+    /// JSON
+    Json,
+}
+
 /// Extraction arguments
 #[derive(Parser, Debug)]
 #[command(
@@ -24,6 +34,98 @@ struct ClangWrapperArgs {
     #[arg(short = 'c', long)]
     compiler: Option<PathBuf>,
 
+    /// Path to `llvm-config`
+    #[arg(long, value_name = "PATH")]
+    llvm_config: Option<PathBuf>,
+
+    /// Path to `clang`
+    #[arg(long, value_name = "PATH")]
+    clang: Option<PathBuf>,
+
+    /// Path to `clang++`
+    #[arg(long, value_name = "PATH")]
+    clangxx: Option<PathBuf>,
+
+    /// Path to `llvm-ar`
+    #[arg(long, value_name = "PATH")]
+    llvm_ar: Option<PathBuf>,
+
+    /// Path to `llvm-link`
+    #[arg(long, value_name = "PATH")]
+    llvm_link: Option<PathBuf>,
+
+    /// Path to `llvm-objcopy`
+    #[arg(long, value_name = "PATH")]
+    llvm_objcopy: Option<PathBuf>,
+
+    /// Absolute path of the directory that stores intermediate bitcode files
+    #[arg(long, value_name = "DIR")]
+    bitcode_store_path: Option<PathBuf>,
+
+    /// Extra linking flags for `llvm-link`, split like shell words
+    #[arg(long, value_name = "FLAGS", allow_hyphen_values = true)]
+    llvm_link_flags: Option<String>,
+
+    /// Extra linking flags for link time optimization, split like shell words
+    #[arg(long, value_name = "FLAGS", allow_hyphen_values = true)]
+    lto_ldflags: Option<String>,
+
+    /// Extra flags for bitcode generation, split like shell words
+    #[arg(long, value_name = "FLAGS", allow_hyphen_values = true)]
+    bitcode_generation_flags: Option<String>,
+
+    /// Skip the bitcode generation
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, default_missing_value = "true")]
+    configure_only: Option<bool>,
+
+    /// Rewrite embedded bitcode filepaths starting with OLD to start with NEW
+    /// instead (repeatable; the last matching map wins)
+    #[arg(long = "bitcode-path-prefix-map", value_name = "OLD=NEW")]
+    bitcode_path_prefix_maps: Vec<PathPrefixMap>,
+
+    /// Lower object files from the generated bitcode in compile-only mode
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, default_missing_value = "true")]
+    single_compile: Option<bool>,
+
+    /// Maximum number of input files processed concurrently, if the GNU make
+    /// jobserver is unavailable
+    #[arg(short = 'j', long)]
+    jobs: Option<usize>,
+
+    /// What to embed into object files
+    #[arg(long, value_enum)]
+    bitcode_embedding: Option<BitcodeEmbedding>,
+
+    /// What to do if the bitcode of a source file cannot be generated
+    #[arg(long, value_enum)]
+    bitcode_generation_policy: Option<BitcodeGenerationPolicy>,
+
+    /// User-defined rules to classify compiler flags, as an inline TOML
+    /// array, e.g., '[{ flag = "-mfoo", arity = 1, role = "compile" }]'
+    #[arg(long, value_name = "TOML")]
+    flag_rules: Option<String>,
+
+    /// Absolute path of the log, to which unrecognized compiler flags are
+    /// appended
+    #[arg(long, value_name = "PATH")]
+    unknown_flags_log: Option<PathBuf>,
+
+    /// Show the effective configuration and where each value comes from,
+    /// and exit
+    #[arg(long)]
+    show_config: bool,
+
+    /// Show how many times each unrecognized compiler flag occurs in the
+    /// unknown-flag log, and exit
+    #[arg(long)]
+    show_unknown_flags: bool,
+
+    /// Show the detected mode, whether the bitcode generation is skipped and
+    /// why, the artifacts and every command that would run, without
+    /// executing anything, and exit
+    #[arg(long, value_enum, value_name = "FORMAT", num_args = 0..=1, default_missing_value = "text")]
+    explain: Option<ExplainFormat>,
+
     /// Verbose mode
     #[arg(short = 'v', long, action = clap::ArgAction::Count)]
     verbose: u8,
@@ -33,13 +135,136 @@ struct ClangWrapperArgs {
     clang_args: Vec<String>,
 }
 
+impl ClangWrapperArgs {
+    /// Obtain the configuration keys overridden by the command-line options
+    fn config_overrides(&self) -> Vec<(&'static str, String)> {
+        let mut overrides = vec![];
+        let filepaths = [
+            ("llvm_config_filepath", &self.llvm_config),
+            ("clang_filepath", &self.clang),
+            ("clangxx_filepath", &self.clangxx),
+            ("llvm_ar_filepath", &self.llvm_ar),
+            ("llvm_link_filepath", &self.llvm_link),
+            ("llvm_objcopy_filepath", &self.llvm_objcopy),
+            ("bitcode_store_path", &self.bitcode_store_path),
+            ("unknown_flags_log", &self.unknown_flags_log),
+        ];
+        for (key, filepath) in filepaths {
+            if let Some(filepath) = filepath {
+                overrides.push((key, filepath.to_string_lossy().into_owned()));
+            }
+        }
+        let flags = [
+            ("llvm_link_flags", &self.llvm_link_flags),
+            ("lto_ldflags", &self.lto_ldflags),
+            ("bitcode_generation_flags", &self.bitcode_generation_flags),
+        ];
+        for (key, flags) in flags {
+            if let Some(flags) = flags {
+                overrides.push((key, flags.clone()));
+            }
+        }
+        if let Some(configure_only) = self.configure_only {
+            overrides.push(("is_configure_only", configure_only.to_string()));
+        }
+        if !self.bitcode_path_prefix_maps.is_empty() {
+            let path_prefix_maps: Vec<_> = self
+                .bitcode_path_prefix_maps
+                .iter()
+                .map(|path_prefix_map| path_prefix_map.to_string())
+                .collect();
+            overrides.push((
+                "bitcode_path_prefix_map",
+                shlex::try_join(path_prefix_maps.iter().map(String::as_str)).unwrap_or_default(),
+            ));
+        }
+        if let Some(single_compile) = self.single_compile {
+            overrides.push(("is_single_compile", single_compile.to_string()));
+        }
+        if let Some(jobs) = self.jobs {
+            overrides.push(("jobs", jobs.to_string()));
+        }
+        if let Some(bitcode_embedding) = self.bitcode_embedding {
+            overrides.push(("bitcode_embedding", value_name(bitcode_embedding)));
+        }
+        if let Some(bitcode_generation_policy) = self.bitcode_generation_policy {
+            overrides.push((
+                "bitcode_generation_policy",
+                value_name(bitcode_generation_policy),
+            ));
+        }
+
+        if let Some(flag_rules) = &self.flag_rules {
+            overrides.push(("flag_rules", flag_rules.clone()));
+        }
+
+        overrides
+    }
+}
+
+/// Obtain the name of the value, as in the configuration file
+fn value_name<T>(value: T) -> String
+where
+    T: ValueEnum,
+{
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
+
+/// Obtain the compiler kind if rllvm is invoked as a drop-in compiler, i.e.,
+/// through a name other than `rllvm-cc` and `rllvm-cxx`, e.g., `cc` or
+/// `rllvm-clang++`
+fn drop_in_compiler_kind() -> Option<CompilerKind> {
+    let program_filepath = PathBuf::from(env::args_os().next()?);
+    let program_name = program_filepath.file_stem()?.to_string_lossy();
+    if ["rllvm-cc", "rllvm-cxx"].contains(&program_name.as_ref()) {
+        return None;
+    }
+
+    Some(CompilerKind::from_program_name(&program_name))
+}
+
+/// Run the drop-in compiler, where all arguments are passed to the wrapped
+/// compiler, and rllvm is only configured by configuration files and
+/// environment variables
+fn drop_in_main(name: &str, compiler_kind: CompilerKind) -> Result<(), Error> {
+    let config = Arc::new(RLLVMConfig::try_new()?);
+    set_rllvm_config(config.clone())?;
+    SimpleLogger::new()
+        .with_level(config.log_level().to_level_filter())
+        .init()
+        .map_err(|err| Error::LoggerError(err.to_string()))?;
+
+    let clang_args: Vec<String> = env::args().skip(1).collect();
+    let mut cc = ClangWrapperBuilder::new()
+        .name(name)
+        .compiler_kind(compiler_kind)
+        .config(config)
+        .build();
+    if let Some(code) = cc.parse_args(&clang_args)?.run()? {
+        std::process::exit(code);
+    }
+
+    Ok(())
+}
+
 pub fn rllvm_main(name: &str, compiler_kind: CompilerKind) -> Result<(), Error> {
+    if let Some(compiler_kind) = drop_in_compiler_kind() {
+        return drop_in_main(name, compiler_kind);
+    }
+
     let args = ClangWrapperArgs::parse();
+    let config = Arc::new(RLLVMConfig::try_new_with_overrides(
+        args.config_overrides(),
+    )?);
+    set_rllvm_config(config.clone())?;
 
     // Set log level
     // The verbose flag will override the configured log level
     let log_level = if args.verbose == 0 {
-        rllvm_config().log_level().to_level_filter()
+        config.log_level().to_level_filter()
     } else {
         LevelFilter::iter()
             .nth(1 + args.verbose as usize)
@@ -50,14 +275,45 @@ pub fn rllvm_main(name: &str, compiler_kind: CompilerKind) -> Result<(), Error>
         .init()
         .map_err(|err| Error::LoggerError(err.to_string()))?;
 
+    if args.show_config {
+        println!("{}", config.to_annotated_toml()?);
+        return Ok(());
+    }
+
+    if args.show_unknown_flags {
+        let unknown_flags_log = config
+            .unknown_flags_log()
+            .ok_or_else(|| Error::InvalidArguments("`unknown_flags_log` is not set".to_string()))?;
+        for (flag, count) in aggregate_unknown_flags(unknown_flags_log)? {
+            println!("{:>8} {}", count, flag);
+        }
+        return Ok(());
+    }
+
+    // A dry run leaves no trace, including in the unknown-flag log
     let mut cc_builder = ClangWrapperBuilder::new()
         .name(name)
-        .compiler_kind(compiler_kind);
+        .compiler_kind(compiler_kind)
+        .record_unknown_flags(args.explain.is_none())
+        .config(config);
     if let Some(compiler) = args.compiler {
         cc_builder = cc_builder.wrapped_compiler(compiler);
     }
     let mut cc = cc_builder.build();
 
+    if let Some(explain_format) = args.explain {
+        let explanation = cc.parse_args(&args.clang_args)?.explain()?;
+        match explain_format {
+            ExplainFormat::Text => print!("{}", explanation),
+            ExplainFormat::Json => println!(
+                "{}",
+                serde_json::to_string_pretty(&explanation)
+                    .map_err(|err| Error::StringError(err.to_string()))?
+            ),
+        }
+        return Ok(());
+    }
+
     if let Some(code) = cc.parse_args(&args.clang_args)?.run()? {
         std::process::exit(code);
     }
@@ -65,6 +321,8 @@ pub fn rllvm_main(name: &str, compiler_kind: CompilerKind) -> Result<(), Error>
     Ok(())
 }
 
-pub fn main() -> Result<(), Error> {
-    rllvm_main("rllvm", CompilerKind::Clang)
+pub fn main() {
+    if let Err(err) = rllvm_main("rllvm", CompilerKind::Clang) {
+        exit_with_error("rllvm-cc", err);
+    }
 }
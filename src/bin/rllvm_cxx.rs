@@ -1,7 +1,9 @@
-use rllvm::{compiler_wrapper::CompilerKind, error::Error};
+use rllvm::{compiler_wrapper::CompilerKind, utils::exit_with_error};
 
 pub mod rllvm_cc;
 
-pub fn main() -> Result<(), Error> {
-    rllvm_cc::rllvm_main("rllvm++", CompilerKind::ClangXX)
+pub fn main() {
+    if let Err(err) = rllvm_cc::rllvm_main("rllvm++", CompilerKind::ClangXX) {
+        exit_with_error("rllvm-cxx", err);
+    }
 }
@@ -1,11 +1,79 @@
-use std::{fs, path::PathBuf};
+use std::{
+    env,
+    fs::{self, DirBuilder},
+    io,
+    path::PathBuf,
+};
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use log::LevelFilter;
 use object::Object;
-use rllvm::{config::rllvm_config, error::Error, utils::*};
+use rllvm::{
+    config::{RLLVMConfig, rllvm_config, set_rllvm_config},
+    error::Error,
+    utils::*,
+};
 use simple_logger::SimpleLogger;
 
+/// Action to take when the extracted bitcode would be stale or incomplete
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum InvalidBitcodePolicy {
+    /// Abort the extraction
+    Error,
+    /// Report the bitcode files and continue
+    Warn,
+}
+
+/// The maximum number of attempts to create a unique temporary directory
+const MAX_TEMPORARY_DIR_CREATION_ATTEMPTS: usize = 64;
+
+/// A temporary directory, which is removed when dropped
+struct TemporaryDir {
+    dirpath: PathBuf,
+}
+
+impl TemporaryDir {
+    /// Create a new directory, only accessible by the current user, under the
+    /// temporary directory of the system
+    ///
+    /// The directory must not exist, so that a directory (or a symbolic link)
+    /// planted by another user is never reused.
+    fn try_new(prefix: &str) -> io::Result<Self> {
+        let mut builder = DirBuilder::new();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+
+        for i in 0..MAX_TEMPORARY_DIR_CREATION_ATTEMPTS {
+            let dirpath = env::temp_dir().join(format!("{}-{}-{}", prefix, std::process::id(), i));
+            match builder.create(&dirpath) {
+                Ok(()) => return Ok(Self { dirpath }),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "Failed to create a unique temporary directory",
+        ))
+    }
+}
+
+impl Drop for TemporaryDir {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_dir_all(&self.dirpath) {
+            log::warn!(
+                "Failed to remove the temporary directory: dirpath={:?}, err={}",
+                self.dirpath,
+                err
+            );
+        }
+    }
+}
+
 /// Extraction arguments
 #[derive(Parser, Debug)]
 #[command(
@@ -30,13 +98,44 @@ struct ExtractionArgs {
     #[arg(short = 'm', long)]
     save_manifest: bool,
 
+    /// Action to take when bitcode files are stale or missing
+    #[arg(long, value_enum, default_value_t = InvalidBitcodePolicy::Error)]
+    on_stale: InvalidBitcodePolicy,
+
+    /// Action to take when source files were built without bitcode, in the
+    /// best-effort bitcode generation mode
+    #[arg(long, value_enum, default_value_t = InvalidBitcodePolicy::Error)]
+    on_incomplete: InvalidBitcodePolicy,
+
+    /// Rewrite embedded bitcode filepaths starting with OLD to start with NEW
+    /// instead (repeatable; the last matching map wins)
+    #[arg(long = "path-map", value_name = "OLD=NEW")]
+    path_maps: Vec<PathPrefixMap>,
+
+    /// Root directory to search for bitcode files that do not exist at their
+    /// (remapped) filepaths (repeatable)
+    #[arg(long = "search-root", value_name = "DIR")]
+    search_roots: Vec<PathBuf>,
+
+    /// Directory to write bitcode modules embedded in self-contained objects
+    /// into (Default: a temporary directory, removed after extraction)
+    #[arg(long)]
+    modules_dir: Option<PathBuf>,
+
     /// Verbose mode
     #[arg(short = 'v', long, action = clap::ArgAction::Count)]
     verbose: u8,
 }
 
-pub fn main() -> Result<(), Error> {
+pub fn main() {
+    if let Err(err) = rllvm_get_bc_main() {
+        exit_with_error("rllvm-get-bc", err);
+    }
+}
+
+fn rllvm_get_bc_main() -> Result<(), Error> {
     let args = ExtractionArgs::parse();
+    set_rllvm_config(RLLVMConfig::try_new()?)?;
 
     // Set log level
     // The verbose flag will override the configured log level
@@ -129,8 +228,15 @@ pub fn main() -> Result<(), Error> {
     )));
 
     // Extract bitcode filepaths
-    let bitcode_filepaths =
-        extract_bitcode_filepaths_from_parsed_objects(&object_files).map_err(|err| {
+    let mut resolver = BitcodePathResolver::new();
+    for path_map in &args.path_maps {
+        resolver = resolver.path_prefix_map(path_map.clone());
+    }
+    for search_root in &args.search_roots {
+        resolver = resolver.search_root(search_root);
+    }
+    let bitcode_records = resolve_bitcode_records_from_parsed_objects(&object_files, &resolver)
+        .map_err(|err| {
             log::error!(
                 "Failed to extract bitcode filepaths: object_files={:?}, err={:?}",
                 object_files,
@@ -138,6 +244,39 @@ pub fn main() -> Result<(), Error> {
             );
             err
         })?;
+
+    // Report source files without bitcode
+    let (missing_records, bitcode_records): (Vec<_>, Vec<_>) = bitcode_records
+        .into_iter()
+        .partition(|record| record.missing_reason().is_some());
+    for missing_record in &missing_records {
+        log::warn!(
+            "Source file without bitcode: {:?}, reason={}",
+            missing_record.filepath(),
+            missing_record.missing_reason().unwrap_or_default()
+        );
+    }
+    if !missing_records.is_empty() {
+        let error_message = format!(
+            "Found {} source file(s) without bitcode: {:?}",
+            missing_records.len(),
+            missing_records
+                .iter()
+                .map(|record| record.filepath())
+                .collect::<Vec<_>>()
+        );
+        if args.on_incomplete == InvalidBitcodePolicy::Error {
+            log::error!("{}", error_message);
+            return Err(Error::IncompleteBitcode(error_message));
+        }
+        log::warn!("{}", error_message);
+    }
+
+    let mut bitcode_filepaths: Vec<_> = bitcode_records
+        .iter()
+        .map(|record| record.filepath().clone())
+        .collect();
+    bitcode_filepaths.dedup();
     if bitcode_filepaths.is_empty() {
         let error_message = format!(
             "No bitcode filepaths found in the input file: {:?}",
@@ -147,6 +286,47 @@ pub fn main() -> Result<(), Error> {
         return Err(Error::MissingFile(error_message));
     }
     log::debug!("Bitcode filepaths: {:?}", bitcode_filepaths);
+
+    // Check bitcode files against their embedded digests
+    let mut invalid_bitcode_files = vec![];
+    for bitcode_record in &bitcode_records {
+        let status = check_bitcode_record(bitcode_record)?;
+        match &status {
+            BitcodeFileStatus::Missing => {
+                log::warn!("Missing bitcode file: {:?}", bitcode_record.filepath());
+            }
+            BitcodeFileStatus::Stale { expected, actual } => {
+                log::warn!(
+                    "Stale bitcode file: {:?}, expected_digest={}, actual_digest={}",
+                    bitcode_record.filepath(),
+                    format_digest(expected),
+                    format_digest(actual)
+                );
+            }
+            BitcodeFileStatus::Unverified => {
+                log::debug!(
+                    "Bitcode file without digest: {:?}",
+                    bitcode_record.filepath()
+                );
+            }
+            BitcodeFileStatus::Intact => {}
+        }
+        if status.is_invalid() {
+            invalid_bitcode_files.push(bitcode_record.filepath().clone());
+        }
+    }
+    if !invalid_bitcode_files.is_empty() {
+        let error_message = format!(
+            "Found {} stale or missing bitcode file(s): {:?}",
+            invalid_bitcode_files.len(),
+            invalid_bitcode_files
+        );
+        if args.on_stale == InvalidBitcodePolicy::Error {
+            log::error!("{}", error_message);
+            return Err(Error::StaleBitcode(error_message));
+        }
+        log::warn!("{}", error_message);
+    }
     if args.save_manifest {
         // Write bitcode filepaths into the manifest file
         let input_parent_dir = input_filepath.parent().unwrap();
@@ -170,6 +350,51 @@ pub fn main() -> Result<(), Error> {
         log::info!("Save manifest: {:?}", manifest_filepath);
     }
 
+    // Materialize bitcode modules embedded in self-contained objects
+    let is_self_contained = bitcode_records.iter().any(|record| record.has_contents());
+    let mut modules_dir_guard = None;
+    let modules_dir = match &args.modules_dir {
+        Some(modules_dir) => {
+            if is_self_contained {
+                fs::create_dir_all(modules_dir).map_err(|err| {
+                    log::error!(
+                        "Failed to create the modules directory: modules_dir={:?}, err={}",
+                        modules_dir,
+                        err
+                    );
+                    err
+                })?;
+            }
+            modules_dir.clone()
+        }
+        None if is_self_contained => {
+            let temporary_dir = TemporaryDir::try_new("rllvm-get-bc").map_err(|err| {
+                log::error!("Failed to create the modules directory: err={}", err);
+                err
+            })?;
+            let modules_dir = temporary_dir.dirpath.clone();
+            // Remove the temporary directory on every exit path
+            modules_dir_guard = Some(temporary_dir);
+            modules_dir
+        }
+        // No module needs to be materialized
+        None => env::temp_dir(),
+    };
+    let mut bitcode_filepaths = vec![];
+    for bitcode_record in &bitcode_records {
+        let bitcode_filepath =
+            materialize_bitcode_record(bitcode_record, &modules_dir).map_err(|err| {
+                log::error!(
+                    "Failed to materialize the bitcode module: filepath={:?}, err={:?}",
+                    bitcode_record.filepath(),
+                    err
+                );
+                err
+            })?;
+        if !bitcode_filepaths.contains(&bitcode_filepath) {
+            bitcode_filepaths.push(bitcode_filepath);
+        }
+    }
     // Link or archive bitcode files
     let merge_bitcode_func = if build_bitcode_archive {
         log::info!("Archive bitcode files");
@@ -178,21 +403,26 @@ pub fn main() -> Result<(), Error> {
         log::info!("Link bitcode files");
         link_bitcode_files
     };
-    if let Some(code) =
-        merge_bitcode_func(&bitcode_filepaths, output_filepath.clone()).map_err(|err| {
-            let merge_action = if build_bitcode_archive {
-                "archive"
-            } else {
-                "link"
-            };
-            log::error!(
-                "Failed to {} bitcode files: bitcode_filepaths={:?}, err={:?}",
-                merge_action,
-                bitcode_filepaths,
+    let merge_result =
+        merge_bitcode_func(rllvm_config(), &bitcode_filepaths, output_filepath.clone()).map_err(
+            |err| {
+                let merge_action = if build_bitcode_archive {
+                    "archive"
+                } else {
+                    "link"
+                };
+                log::error!(
+                    "Failed to {} bitcode files: bitcode_filepaths={:?}, err={:?}",
+                    merge_action,
+                    bitcode_filepaths,
+                    err
+                );
                 err
-            );
-            err
-        })?
+            },
+        );
+    // `std::process::exit` does not run destructors
+    drop(modules_dir_guard);
+    if let Some(code) = merge_result?
         && code != 0
     {
         std::process::exit(code);
@@ -0,0 +1,164 @@
+//! Planned command pipeline of the compiler wrapper
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::arg_parser::CompileMode;
+
+/// A command that the compiler wrapper would run
+#[derive(Serialize, Debug, Clone)]
+pub struct PlannedCommand {
+    /// The mode of the command
+    mode: CompileMode,
+    /// The command, including the program
+    args: Vec<String>,
+}
+
+impl PlannedCommand {
+    pub(crate) fn new(mode: CompileMode, args: Vec<String>) -> Self {
+        Self { mode, args }
+    }
+
+    pub fn mode(&self) -> &CompileMode {
+        &self.mode
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+}
+
+/// Filepaths of the artifacts of one input file
+#[derive(Serialize, Debug, Clone)]
+pub struct PlannedArtifact {
+    /// The absolute filepath of the input file
+    src_filepath: PathBuf,
+    /// The object file, into which the bitcode is embedded
+    object_filepath: PathBuf,
+    /// The bitcode file
+    bitcode_filepath: PathBuf,
+}
+
+impl PlannedArtifact {
+    pub(crate) fn new(
+        src_filepath: PathBuf,
+        object_filepath: PathBuf,
+        bitcode_filepath: PathBuf,
+    ) -> Self {
+        Self {
+            src_filepath,
+            object_filepath,
+            bitcode_filepath,
+        }
+    }
+
+    pub fn src_filepath(&self) -> &Path {
+        &self.src_filepath
+    }
+
+    pub fn object_filepath(&self) -> &Path {
+        &self.object_filepath
+    }
+
+    pub fn bitcode_filepath(&self) -> &Path {
+        &self.bitcode_filepath
+    }
+}
+
+/// What the compiler wrapper would do for the given arguments, without
+/// executing anything (see [`CompilerWrapper::explain`])
+///
+/// [`CompilerWrapper::explain`]: crate::compiler_wrapper::CompilerWrapper::explain
+#[derive(Serialize, Debug, Clone)]
+pub struct Explanation {
+    /// The detected mode of the compiler invocation
+    mode: CompileMode,
+    /// The reason why the bitcode generation is skipped, if it is
+    bitcode_generation_skip_reason: Option<&'static str>,
+    /// Whether object files are lowered from the generated bitcode
+    is_single_compile: bool,
+    /// Artifacts of the input files
+    artifacts: Vec<PlannedArtifact>,
+    /// Commands in the order that they would run, where commands of different
+    /// input files may run concurrently
+    commands: Vec<PlannedCommand>,
+}
+
+impl Explanation {
+    pub(crate) fn new(
+        mode: CompileMode,
+        bitcode_generation_skip_reason: Option<&'static str>,
+        is_single_compile: bool,
+    ) -> Self {
+        Self {
+            mode,
+            bitcode_generation_skip_reason,
+            is_single_compile,
+            artifacts: vec![],
+            commands: vec![],
+        }
+    }
+
+    /// Append an artifact
+    pub(crate) fn push_artifact(&mut self, artifact: PlannedArtifact) -> &'_ mut Self {
+        self.artifacts.push(artifact);
+        self
+    }
+
+    /// Append a command
+    pub(crate) fn push_command(&mut self, mode: CompileMode, args: Vec<String>) -> &'_ mut Self {
+        self.commands.push(PlannedCommand::new(mode, args));
+        self
+    }
+
+    pub fn mode(&self) -> &CompileMode {
+        &self.mode
+    }
+
+    pub fn bitcode_generation_skip_reason(&self) -> Option<&'static str> {
+        self.bitcode_generation_skip_reason
+    }
+
+    pub fn is_single_compile(&self) -> bool {
+        self.is_single_compile
+    }
+
+    pub fn artifacts(&self) -> &[PlannedArtifact] {
+        &self.artifacts
+    }
+
+    pub fn commands(&self) -> &[PlannedCommand] {
+        &self.commands
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "mode: {:?}", self.mode)?;
+        match self.bitcode_generation_skip_reason {
+            Some(reason) => writeln!(f, "bitcode generation: skipped, as {}", reason)?,
+            None => writeln!(f, "bitcode generation: enabled")?,
+        }
+        writeln!(f, "single compile: {}", self.is_single_compile)?;
+
+        writeln!(f, "artifacts:")?;
+        for artifact in &self.artifacts {
+            writeln!(f, "  - source: {}", artifact.src_filepath.display())?;
+            writeln!(f, "    object: {}", artifact.object_filepath.display())?;
+            writeln!(f, "    bitcode: {}", artifact.bitcode_filepath.display())?;
+        }
+
+        writeln!(f, "commands:")?;
+        for command in &self.commands {
+            let args = shlex::try_join(command.args.iter().map(String::as_str))
+                .unwrap_or_else(|_| command.args.join(" "));
+            writeln!(f, "  [{:?}] {}", command.mode, args)?;
+        }
+
+        Ok(())
+    }
+}
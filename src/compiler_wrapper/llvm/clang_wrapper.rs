@@ -1,9 +1,16 @@
 //! Clang compiler wrapper
 
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use crate::{
-    arg_parser::CompilerArgsInfo, compiler_wrapper::*, config::rllvm_config, error::Error,
+    arg_parser::{CompilerArgsInfo, flag_rule_patterns},
+    compiler_wrapper::*,
+    config::{BitcodeEmbedding, RLLVMConfig, shared_rllvm_config},
+    error::Error,
+    utils::append_unknown_flags,
 };
 
 #[derive(Debug)]
@@ -12,6 +19,9 @@ pub struct ClangWrapper {
     wrapped_compiler: PathBuf,
     compiler_kind: CompilerKind,
     is_silent: bool,
+    bitcode_embedding: BitcodeEmbedding,
+    is_unknown_flags_recorded: bool,
+    config: Arc<RLLVMConfig>,
 
     is_parse_args_called: bool,
 
@@ -20,20 +30,10 @@ pub struct ClangWrapper {
 
 impl ClangWrapper {
     pub fn new(name: &str, compiler_kind: CompilerKind) -> Self {
-        // Obtain the compiler path from the configuration
-        let compiler_path = match compiler_kind {
-            CompilerKind::Clang => rllvm_config().clang_filepath(),
-            CompilerKind::ClangXX => rllvm_config().clangxx_filepath(),
-        };
-
-        Self {
-            name: name.to_string(),
-            wrapped_compiler: compiler_path.clone(),
-            compiler_kind,
-            is_silent: false,
-            is_parse_args_called: false,
-            args: CompilerArgsInfo::default(),
-        }
+        ClangWrapperBuilder::new()
+            .name(name)
+            .compiler_kind(compiler_kind)
+            .build()
     }
 }
 
@@ -68,7 +68,33 @@ impl CompilerWrapper for ClangWrapper {
         }
         self.is_parse_args_called = true;
 
-        self.args.parse_args(args)?;
+        let flag_rules = flag_rule_patterns(self.config.flag_rules())?;
+        self.args.parse_args_with_rules(args, &flag_rules)?;
+
+        // Record unknown flags for the audit
+        if self.is_unknown_flags_recorded
+            && let Some(unknown_flags_log) = self.config.unknown_flags_log()
+            && !self.args.unknown_flags().is_empty()
+            && let Err(err) = append_unknown_flags(unknown_flags_log, self.args.unknown_flags())
+        {
+            log::warn!(
+                "Failed to record unknown flags: {:?}, err={}",
+                unknown_flags_log,
+                err
+            );
+        }
+
+        Ok(self)
+    }
+
+    fn set_args(&mut self, args: CompilerArgsInfo) -> Result<&'_ mut Self, Error> {
+        if self.is_parse_args_called {
+            return Err(Error::Unknown(
+                "The arguments cannot be set twice on the same instance".to_string(),
+            ));
+        }
+        self.is_parse_args_called = true;
+        self.args = args;
 
         Ok(self)
     }
@@ -85,6 +111,14 @@ impl CompilerWrapper for ClangWrapper {
     fn is_silent(&self) -> bool {
         self.is_silent
     }
+
+    fn config(&self) -> &RLLVMConfig {
+        &self.config
+    }
+
+    fn bitcode_embedding(&self) -> BitcodeEmbedding {
+        self.bitcode_embedding
+    }
 }
 
 /// Builder for the [`ClangWrapper`]
@@ -98,6 +132,12 @@ pub struct ClangWrapperBuilder {
     compiler_kind: CompilerKind,
     /// Silence the compiler wrapper output (optional)
     is_silent: Option<bool>,
+    /// How the bitcode is embedded into object files (optional)
+    bitcode_embedding: Option<BitcodeEmbedding>,
+    /// Record unknown flags in the unknown-flag log (optional)
+    is_unknown_flags_recorded: Option<bool>,
+    /// Configuration (optional; the global configuration by default)
+    config: Option<Arc<RLLVMConfig>>,
 }
 
 impl Default for ClangWrapperBuilder {
@@ -107,6 +147,9 @@ impl Default for ClangWrapperBuilder {
             wrapped_compiler: None,
             compiler_kind: CompilerKind::Clang,
             is_silent: None,
+            bitcode_embedding: None,
+            is_unknown_flags_recorded: None,
+            config: None,
         }
     }
 }
@@ -121,13 +164,18 @@ impl CompilerWrapperBuilder for ClangWrapperBuilder {
     type OutputType = ClangWrapper;
 
     fn build(&self) -> Self::OutputType {
+        let config = self
+            .config
+            .clone()
+            .unwrap_or_else(|| shared_rllvm_config().clone());
+
         // Obtain the compiler path from the configuration, if not provided
         let compiler_path = self
             .wrapped_compiler
             .as_ref()
             .unwrap_or(match self.compiler_kind {
-                CompilerKind::Clang => rllvm_config().clang_filepath(),
-                CompilerKind::ClangXX => rllvm_config().clangxx_filepath(),
+                CompilerKind::Clang => config.clang_filepath(),
+                CompilerKind::ClangXX => config.clangxx_filepath(),
             });
 
         ClangWrapper {
@@ -135,6 +183,9 @@ impl CompilerWrapperBuilder for ClangWrapperBuilder {
             wrapped_compiler: compiler_path.clone(),
             compiler_kind: self.compiler_kind,
             is_silent: self.is_silent.unwrap_or(false),
+            bitcode_embedding: self.bitcode_embedding.unwrap_or(config.bitcode_embedding()),
+            is_unknown_flags_recorded: self.is_unknown_flags_recorded.unwrap_or(true),
+            config,
             is_parse_args_called: false,
             args: CompilerArgsInfo::default(),
         }
@@ -162,4 +213,267 @@ impl CompilerWrapperBuilder for ClangWrapperBuilder {
         self.is_silent = Some(value);
         self
     }
+
+    fn bitcode_embedding(mut self, bitcode_embedding: BitcodeEmbedding) -> Self {
+        self.bitcode_embedding = Some(bitcode_embedding);
+        self
+    }
+
+    fn record_unknown_flags(mut self, value: bool) -> Self {
+        self.is_unknown_flags_recorded = Some(value);
+        self
+    }
+
+    fn config<C>(mut self, config: C) -> Self
+    where
+        C: Into<Arc<RLLVMConfig>>,
+    {
+        self.config = Some(config.into());
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arg_parser::CompileMode;
+    use std::fs;
+
+    #[test]
+    fn test_wrapper_config() {
+        let config_filepath = Path::new("/tmp/rllvm_test_wrapper_config.toml");
+        fs::write(
+            config_filepath,
+            r#"
+llvm_config_filepath = '/usr/bin/llvm-config'
+clang_filepath = '/usr/bin/true'
+clangxx_filepath = '/usr/bin/true'
+llvm_ar_filepath = '/usr/bin/llvm-ar'
+llvm_link_filepath = '/usr/bin/llvm-link'
+llvm_objcopy_filepath = '/usr/bin/llvm-objcopy'
+is_configure_only = true
+unknown_flags_log = '/tmp/rllvm_test_wrapper_config.log'
+"#,
+        )
+        .expect("Failed to write the config file");
+        let config = RLLVMConfig::try_load(config_filepath).expect("Failed to load the config");
+
+        // The wrapper does not touch the global configuration
+        let mut cc = ClangWrapperBuilder::new()
+            .name("rllvm")
+            .compiler_kind(CompilerKind::Clang)
+            .config(config)
+            .build();
+        assert_eq!(cc.wrapped_compiler(), Path::new("/usr/bin/true"));
+        assert_eq!(cc.bitcode_embedding(), BitcodeEmbedding::Filepath);
+        assert_eq!(
+            cc.parse_args(&["-c", "foo.c"]).unwrap().run().unwrap(),
+            Some(0)
+        );
+        assert!(cc.args().is_bitcode_generation_skipped(cc.config()));
+
+        // Unknown flags are only recorded if requested
+        let unknown_flags_log = Path::new("/tmp/rllvm_test_wrapper_config.log");
+        let _ = fs::remove_file(unknown_flags_log);
+        let config = Arc::new(cc.config().clone());
+        for is_recorded in [false, true] {
+            ClangWrapperBuilder::new()
+                .name("rllvm")
+                .record_unknown_flags(is_recorded)
+                .config(config.clone())
+                .build()
+                .parse_args(&["-c", "-mfoo", "foo.c"])
+                .expect("Failed to parse arguments");
+            assert_eq!(unknown_flags_log.exists(), is_recorded);
+        }
+
+        // Clean
+        fs::remove_file(unknown_flags_log).expect("Failed to delete the log");
+        fs::remove_file(config_filepath).expect("Failed to delete the config file");
+    }
+
+    #[test]
+    fn test_explain() {
+        let dir = Path::new("/tmp/rllvm_test_explain");
+        fs::create_dir_all(dir).expect("Failed to create the directory");
+        let config_filepath = dir.join("config.toml");
+        fs::write(
+            &config_filepath,
+            r#"
+llvm_config_filepath = '/usr/bin/llvm-config'
+clang_filepath = '/usr/bin/false'
+clangxx_filepath = '/usr/bin/false'
+llvm_ar_filepath = '/usr/bin/llvm-ar'
+llvm_link_filepath = '/usr/bin/llvm-link'
+llvm_objcopy_filepath = '/usr/bin/llvm-objcopy'
+"#,
+        )
+        .expect("Failed to write the config file");
+        let src_filepath = dir.join("foo.c");
+        fs::write(&src_filepath, "int main() { return 0; }\n")
+            .expect("Failed to write the source file");
+        let src_file = src_filepath.to_string_lossy().into_owned();
+        let config =
+            Arc::new(RLLVMConfig::try_load(&config_filepath).expect("Failed to load the config"));
+        let explain = |args: &[&str]| {
+            ClangWrapperBuilder::new()
+                .name("rllvm")
+                .config(config.clone())
+                .build()
+                .parse_args(args)
+                .expect("Failed to parse arguments")
+                .explain()
+                .expect("Failed to explain")
+        };
+        let modes = |explanation: &Explanation| -> Vec<CompileMode> {
+            explanation
+                .commands()
+                .iter()
+                .map(|command| *command.mode())
+                .collect()
+        };
+
+        // Compile only: the user's compile, and then the bitcode generation
+        let explanation = explain(&["-c", &src_file, "-o", "/tmp/rllvm_test_explain/foo.o"]);
+        assert_eq!(*explanation.mode(), CompileMode::Compiling);
+        assert_eq!(explanation.bitcode_generation_skip_reason(), None);
+        assert_eq!(explanation.artifacts().len(), 1);
+        assert_eq!(
+            explanation.artifacts()[0].object_filepath(),
+            dir.join("foo.o")
+        );
+        assert_eq!(
+            modes(&explanation),
+            [CompileMode::Compiling, CompileMode::BitcodeGeneration]
+        );
+        assert_eq!(explanation.commands()[0].args()[0], "/usr/bin/false");
+
+        // Compile and link: each object file is built separately, and then
+        // linked in place of the source file
+        let explanation = explain(&[&src_file, "-o", "/tmp/rllvm_test_explain/foo", "-lm"]);
+        assert_eq!(
+            modes(&explanation),
+            [
+                CompileMode::Compiling,
+                CompileMode::BitcodeGeneration,
+                CompileMode::Linking
+            ]
+        );
+        let object_file = explanation.artifacts()[0]
+            .object_filepath()
+            .to_string_lossy()
+            .into_owned();
+        let link_args = explanation.commands()[2].args();
+        assert!(link_args.contains(&object_file));
+        assert!(!link_args.contains(&src_file));
+
+        // Dependency files are written when building the object file, but not
+        // when generating the bitcode
+        let explanation = explain(&[
+            "-MD",
+            "-MF",
+            "/tmp/rllvm_test_explain/foo.d",
+            &src_file,
+            "-o",
+            "/tmp/rllvm_test_explain/foo",
+        ]);
+        assert_eq!(explanation.bitcode_generation_skip_reason(), None);
+        let dependency_args = ["-MD", "-MF", "/tmp/rllvm_test_explain/foo.d"].map(String::from);
+        let commands = explanation.commands();
+        assert_eq!(*commands[0].mode(), CompileMode::Compiling);
+        assert!(
+            commands[0]
+                .args()
+                .windows(dependency_args.len())
+                .any(|window| window == dependency_args)
+        );
+        assert_eq!(*commands[1].mode(), CompileMode::BitcodeGeneration);
+        assert!(!commands[1].args().contains(&dependency_args[2]));
+
+        // Skipped: only the user's command
+        let explanation = explain(&["-E", &src_file]);
+        assert_eq!(
+            explanation.bitcode_generation_skip_reason(),
+            Some("we are only preprocessing")
+        );
+        assert!(explanation.artifacts().is_empty());
+        assert_eq!(modes(&explanation), [CompileMode::Compiling]);
+        assert!(
+            explanation
+                .to_string()
+                .contains("skipped, as we are only preprocessing")
+        );
+
+        fs::write(
+            &config_filepath,
+            fs::read_to_string(&config_filepath).unwrap() + "is_single_compile = true\n",
+        )
+        .expect("Failed to write the config file");
+        let single_compile_config =
+            RLLVMConfig::try_load(&config_filepath).expect("Failed to load the config");
+        let single_compile_config = Arc::new(single_compile_config);
+        let explain_single_compile = |args: &[&str]| {
+            ClangWrapperBuilder::new()
+                .name("rllvm")
+                .config(single_compile_config.clone())
+                .build()
+                .parse_args(args)
+                .expect("Failed to parse arguments")
+                .explain()
+                .expect("Failed to explain")
+        };
+        assert!(explain_single_compile(&["-c", &src_file]).is_single_compile());
+        let explanation =
+            explain_single_compile(&["-c", "-Wp,-MMD,/tmp/rllvm_test_explain/.foo.o.d", &src_file]);
+        assert!(!explanation.is_single_compile());
+        assert_eq!(
+            modes(&explanation),
+            [CompileMode::Compiling, CompileMode::BitcodeGeneration]
+        );
+        assert!(
+            explanation.commands()[0]
+                .args()
+                .contains(&"-Wp,-MMD,/tmp/rllvm_test_explain/.foo.o.d".to_string())
+        );
+
+        // Arguments serialized by another tool
+        let mut args = CompilerArgsInfo::default();
+        args.parse_args(&["-c", &src_file])
+            .expect("Failed to parse arguments");
+        let args = serde_json::to_string(&args).expect("Failed to serialize");
+        let mut cc = ClangWrapperBuilder::new()
+            .name("rllvm")
+            .config(config.clone())
+            .build();
+        let explanation = cc
+            .set_args(serde_json::from_str(&args).expect("Failed to deserialize"))
+            .expect("Failed to set arguments")
+            .explain()
+            .expect("Failed to explain");
+        assert_eq!(
+            modes(&explanation),
+            [CompileMode::Compiling, CompileMode::BitcodeGeneration]
+        );
+        assert!(cc.parse_args(&["-c", &src_file]).is_err());
+
+        // Clean
+        fs::remove_dir_all(dir).expect("Failed to delete the directory");
+    }
+
+    #[test]
+    fn test_compiler_kind_from_program_name() {
+        for name in ["cc", "clang", "clang-17", "clang-17.0", "gcc", "rllvm-cc"] {
+            assert_eq!(CompilerKind::from_program_name(name), CompilerKind::Clang);
+        }
+        for name in [
+            "c++",
+            "clang++",
+            "clang++-17",
+            "g++",
+            "rllvm-clang++",
+            "rllvm-cxx",
+        ] {
+            assert_eq!(CompilerKind::from_program_name(name), CompilerKind::ClangXX);
+        }
+    }
 }
@@ -4,5 +4,9 @@
 mod wrapper;
 pub use wrapper::*;
 
+/// Planned command pipeline, for the dry run
+mod explanation;
+pub use explanation::*;
+
 /// LLVM compiler wrapper (clang/clang++)
 pub mod llvm;
@@ -1,16 +1,16 @@
 //! Genera interfaces for the compiler wrapper
 
-use std::{
-    collections::HashSet,
-    ffi::OsStr,
-    path::{Path, PathBuf},
-};
+use std::{collections::HashSet, ffi::OsStr, path::Path, sync::Arc};
 
 use crate::{
-    arg_parser::{CompileMode, CompilerArgsInfo},
-    config::rllvm_config,
+    arg_parser::{CompileMode, CompilerArgsInfo, source_file_language},
+    compiler_wrapper::{Explanation, PlannedArtifact},
+    config::{BitcodeEmbedding, BitcodeGenerationPolicy, RLLVMConfig},
     error::Error,
-    utils::{embed_bitcode_filepath_to_object_file, execute_command_for_status},
+    utils::{
+        ResponseFile, embed_bitcode_to_object_file, embed_missing_bitcode_to_object_file,
+        execute_command_for_status, run_jobs,
+    },
 };
 
 /// Compiler type
@@ -23,8 +23,40 @@ pub enum CompilerKind {
     ClangXX,
 }
 
+impl CompilerKind {
+    /// Obtain the compiler kind from the name of the invoked program, e.g.,
+    /// `clang++`, `c++` and `rllvm-cxx` are C++ compilers, while `cc`, `clang`
+    /// and `clang-17` are C compilers
+    pub fn from_program_name(program_name: &str) -> Self {
+        // Strip the version suffix, e.g., `clang++-17`
+        let program_name = program_name
+            .trim_end_matches(|c: char| c.is_ascii_digit() || c == '.')
+            .trim_end_matches('-');
+        if program_name.ends_with("++") || program_name.ends_with("cxx") {
+            Self::ClangXX
+        } else {
+            Self::Clang
+        }
+    }
+}
+
+/// Arguments that set the language of the following input file, if any
+fn language_args(language: Option<&str>) -> Vec<String> {
+    language
+        .map(|language| vec!["-x".to_string(), language.to_string()])
+        .unwrap_or_default()
+}
+
+/// Returns `true` if the input file is a bitcode or textual IR, which is used
+/// as the bitcode directly
+fn is_ir_input(src_filepath: &Path, language: Option<&str>) -> bool {
+    language
+        .or_else(|| source_file_language(&src_filepath.to_string_lossy()))
+        .is_some_and(|language| language == "ir")
+}
+
 /// A general interface that wraps different compilers
-pub trait CompilerWrapper {
+pub trait CompilerWrapper: Sync {
     /// Obtain the name of the wrapper
     fn name(&self) -> &str;
 
@@ -39,9 +71,16 @@ pub trait CompilerWrapper {
     where
         S: AsRef<str>;
 
+    /// Set the wrapper arguments from the argument information, e.g., one
+    /// deserialized from another tool, instead of parsing them
+    fn set_args(&mut self, args: CompilerArgsInfo) -> Result<&'_ mut Self, Error>;
+
     /// Obtain the argument information
     fn args(&self) -> &CompilerArgsInfo;
 
+    /// Obtain the configuration of the wrapper
+    fn config(&self) -> &RLLVMConfig;
+
     /// Command to run the compiler
     fn command(&self) -> Result<Vec<String>, Error> {
         let args_info = self.args();
@@ -53,7 +92,7 @@ pub trait CompilerWrapper {
             // Linking
             if args_info.is_lto() {
                 // Add LTO LDFLAGS
-                if let Some(lto_ldflags) = rllvm_config().lto_ldflags() {
+                if let Some(lto_ldflags) = self.config().lto_ldflags() {
                     args.extend(lto_ldflags.iter().cloned());
                 }
             }
@@ -78,20 +117,130 @@ pub trait CompilerWrapper {
     /// Returns `true` if `silence` was called with `true`
     fn is_silent(&self) -> bool;
 
+    /// Obtain how the bitcode is embedded into object files
+    fn bitcode_embedding(&self) -> BitcodeEmbedding;
+
+    /// Returns `true` if object files are lowered from the generated bitcode,
+    /// instead of compiling the sources twice
+    ///
+    /// This only applies to compile-only mode, and falls back to compiling
+    /// twice if the lowered object file may differ from the compiled one.
+    fn is_single_compile(&self) -> bool {
+        let args_info = self.args();
+        if !self.config().is_single_compile()
+            || !args_info.is_compile_only()
+            || args_info
+                .bitcode_generation_skip_reason(self.config())
+                .is_some()
+        {
+            return false;
+        }
+
+        if self
+            .config()
+            .bitcode_generation_flags()
+            .is_some_and(|flags| !flags.is_empty())
+        {
+            log::info!("Compile twice, as extra bitcode generation flags are provided");
+            return false;
+        }
+        if let Some(flag) = args_info.single_compile_incompatible_flag() {
+            log::info!("Compile twice, as the flag is not supported: {}", flag);
+            return false;
+        }
+
+        true
+    }
+
     /// Run the compiler
     fn run(&mut self) -> Result<Option<i32>, Error> {
-        if let Some(code) = self.build_target()?
-            && code != 0
+        if self.is_single_compile() {
+            // The object files will be lowered from the bitcode
+            return self.generate_bitcode_files_and_embed_filepaths();
+        }
+        if !self.args().is_compile_only()
+            && self
+                .args()
+                .bitcode_generation_skip_reason(self.config())
+                .is_none()
         {
-            return Ok(Some(code));
+            // The object files will be built and embedded separately, and then
+            // linked only once
+            return self.generate_bitcode_files_and_embed_filepaths();
         }
-        if self.args().is_bitcode_generation_skipped() {
+
+        // A failure of the user's command is returned as an error
+        self.build_target()?;
+        if self.args().is_bitcode_generation_skipped(self.config()) {
             return Ok(Some(0));
         }
 
         self.generate_bitcode_files_and_embed_filepaths()
     }
 
+    /// Plan what [`run`](Self::run) would do, without executing anything
+    fn explain(&self) -> Result<Explanation, Error> {
+        let args_info = self.args();
+        let is_compile_only = args_info.is_compile_only();
+        let skip_reason = args_info.bitcode_generation_skip_reason(self.config());
+        let is_single_compile = self.is_single_compile();
+        let mut explanation = Explanation::new(args_info.mode(), skip_reason, is_single_compile);
+
+        // Follow the same pipeline as `run`
+        let is_built_separately = is_single_compile || (!is_compile_only && skip_reason.is_none());
+        if !is_built_separately {
+            explanation.push_command(args_info.mode(), self.command()?);
+        }
+        if skip_reason.is_some() {
+            return Ok(explanation);
+        }
+
+        let artifact_filepaths = args_info.artifact_filepaths(self.config())?;
+        let mut object_filepaths = vec![];
+        for ((src_filepath, object_filepath, bitcode_filepath), language) in artifact_filepaths
+            .into_iter()
+            .zip(args_info.input_file_languages())
+        {
+            let language = language.as_deref();
+            if !is_compile_only {
+                explanation.push_command(
+                    CompileMode::Compiling,
+                    self.build_object_file_command(&src_filepath, &object_filepath, language),
+                );
+            }
+            let src_bitcode_filepath = if is_ir_input(&src_filepath, language) {
+                &src_filepath
+            } else {
+                explanation.push_command(
+                    CompileMode::BitcodeGeneration,
+                    self.generate_bitcode_file_command(&src_filepath, &bitcode_filepath, language),
+                );
+                &bitcode_filepath
+            };
+            if is_single_compile {
+                explanation.push_command(
+                    CompileMode::BitcodeLowering,
+                    self.lower_bitcode_file_command(src_bitcode_filepath, &object_filepath),
+                );
+            }
+
+            object_filepaths.push(object_filepath.clone());
+            explanation.push_artifact(PlannedArtifact::new(
+                src_filepath,
+                object_filepath,
+                bitcode_filepath,
+            ));
+        }
+        if !is_compile_only {
+            explanation.push_command(
+                CompileMode::Linking,
+                self.link_object_files_command(&object_filepaths)?,
+            );
+        }
+
+        Ok(explanation)
+    }
+
     fn execute_command<S>(&self, args: &[S], mode: CompileMode) -> Result<Option<i32>, Error>
     where
         S: AsRef<OsStr> + std::fmt::Debug,
@@ -104,16 +253,34 @@ pub trait CompilerWrapper {
                 "The number of arguments cannot be 0".into(),
             ));
         }
-        let status = execute_command_for_status(args[0].as_ref(), &args[1..])?;
+        // Pass long arguments by a response file, which is deleted after the
+        // execution
+        let response_file = match args[1..]
+            .iter()
+            .map(|arg| arg.as_ref().to_str())
+            .collect::<Option<Vec<_>>>()
+        {
+            Some(str_args) => ResponseFile::try_new_if_too_long(&str_args)?,
+            None => None,
+        };
+        let status = match &response_file {
+            Some(response_file) => {
+                execute_command_for_status(args[0].as_ref(), &[response_file.arg()])?
+            }
+            None => execute_command_for_status(args[0].as_ref(), &args[1..])?,
+        };
         if !self.is_silent() {
             log::debug!("[{:?}] exit_status={}", mode, status);
         }
 
         if !status.success() {
-            return Err(Error::ExecutionFailure(format!(
-                "Failed to execute the command: args={:?}, exit_status={}",
-                args, status
-            )));
+            return Err(Error::CommandFailure {
+                command: args
+                    .iter()
+                    .map(|arg| arg.as_ref().to_string_lossy().into_owned())
+                    .collect(),
+                status,
+            });
         }
 
         Ok(status.code())
@@ -128,39 +295,153 @@ pub trait CompilerWrapper {
     }
 
     /// Generate bitcode files for all input files
+    ///
+    /// Input files are processed concurrently (see [`run_jobs`]), and all
+    /// failures are reported together.
     fn generate_bitcode_files_and_embed_filepaths(&self) -> Result<Option<i32>, Error> {
         let is_compile_only = self.args().is_compile_only();
-        let artifact_filepaths = self.args().artifact_filepaths()?;
-        let mut object_filepaths = vec![];
-        for (src_filepath, object_filepath, bitcode_filepath) in artifact_filepaths {
-            if !is_compile_only {
-                // We need to explicitly build the intermediate object file
-                self.build_object_file(&src_filepath, &object_filepath)?;
+        let is_single_compile = self.is_single_compile();
+        let artifact_filepaths = self.args().artifact_filepaths(self.config())?;
+        let object_filepaths: Vec<_> = artifact_filepaths
+            .iter()
+            .map(|(_, object_filepath, _)| object_filepath.clone())
+            .collect();
+
+        let artifacts = artifact_filepaths
+            .into_iter()
+            .zip(self.args().input_file_languages())
+            .collect();
+
+        let results = run_jobs(
+            artifacts,
+            self.config().jobs(),
+            |((src_filepath, object_filepath, bitcode_filepath), language)| {
+                self.build_artifacts(
+                    &src_filepath,
+                    &object_filepath,
+                    &bitcode_filepath,
+                    language.as_deref(),
+                    is_single_compile,
+                )
+            },
+        );
+        let mut errors: Vec<_> = results.into_iter().filter_map(Result::err).collect();
+        if errors.len() == 1 {
+            return Err(errors.remove(0));
+        }
+        if !errors.is_empty() {
+            return Err(Error::Aggregated(errors));
+        }
 
-                // Collect all intermediate object files
-                object_filepaths.push(object_filepath.clone());
-            }
+        if is_compile_only {
+            // The compiler has generated the object files, which now embed
+            // the bitcode, so there is nothing to link
+            return Ok(Some(0));
+        }
 
-            let src_bitcode_filepath = if src_filepath.extension().is_some_and(|x| x == "bc") {
-                // The source file is a bitcode; therefore, we do not need to
-                // generate the bitcode and directly use the source file
-                src_filepath
-            } else {
-                // Generate the bitcode
-                if let Some(code) = self.generate_bitcode_file(&src_filepath, &bitcode_filepath)?
-                    && code != 0
-                {
-                    return Ok(Some(code));
+        self.link_object_files(&object_filepaths)
+    }
+
+    /// Build the object file (unless in compile-only mode) and the bitcode
+    /// file for one input file, and embed the bitcode into the object file
+    ///
+    /// `language` is the language of the input file given by `-x`, if any.
+    fn build_artifacts(
+        &self,
+        src_filepath: &Path,
+        object_filepath: &Path,
+        bitcode_filepath: &Path,
+        language: Option<&str>,
+        is_single_compile: bool,
+    ) -> Result<Option<i32>, Error> {
+        if !self.args().is_compile_only() {
+            // We need to explicitly build the intermediate object file
+            self.build_object_file(src_filepath, object_filepath, language)?;
+        }
+
+        let is_best_effort =
+            self.config().bitcode_generation_policy() == BitcodeGenerationPolicy::BestEffort;
+        let src_bitcode_filepath = if is_ir_input(src_filepath, language) {
+            // The source file is a bitcode or textual IR; therefore, we do not
+            // need to generate the bitcode and directly use the source file
+            src_filepath
+        } else {
+            // Generate the bitcode
+            match self.generate_bitcode_file(src_filepath, bitcode_filepath, language) {
+                Ok(_) => {}
+                Err(err) if is_best_effort => {
+                    return self.skip_missing_bitcode(
+                        src_filepath,
+                        object_filepath,
+                        language,
+                        is_single_compile,
+                        err,
+                    );
                 }
-                bitcode_filepath
-            };
+                Err(err) => return Err(err),
+            }
+            bitcode_filepath
+        };
+
+        if is_single_compile {
+            // Lower the bitcode to the object file, instead of compiling the
+            // source file again
+            match self.lower_bitcode_file(src_bitcode_filepath, object_filepath) {
+                Ok(_) => {}
+                Err(err) if is_best_effort => {
+                    return self.skip_missing_bitcode(
+                        src_filepath,
+                        object_filepath,
+                        language,
+                        is_single_compile,
+                        err,
+                    );
+                }
+                Err(err) => return Err(err),
+            }
+        }
 
-            // Embed the path of the bitcode to the corresponding object file
-            embed_bitcode_filepath_to_object_file(&src_bitcode_filepath, &object_filepath, None)?;
+        // Embed the bitcode to the corresponding object file
+        embed_bitcode_to_object_file(
+            src_bitcode_filepath,
+            object_filepath,
+            None,
+            self.bitcode_embedding(),
+            &self.config().bitcode_path_prefix_maps(),
+        )?;
+
+        Ok(Some(0))
+    }
+
+    /// Continue without the bitcode of one input file, and record that it is
+    /// missing in the object file, in best-effort mode
+    fn skip_missing_bitcode(
+        &self,
+        src_filepath: &Path,
+        object_filepath: &Path,
+        language: Option<&str>,
+        is_single_compile: bool,
+        err: Error,
+    ) -> Result<Option<i32>, Error> {
+        log::warn!(
+            "Continue without the bitcode: src_filepath={:?}, err={}",
+            src_filepath,
+            err
+        );
+        if is_single_compile {
+            // The object file cannot be lowered from the bitcode, so we need to
+            // compile the source file instead
+            self.build_object_file(src_filepath, object_filepath, language)?;
         }
 
-        let output_filepath = PathBuf::from(self.args().output_filename()).canonicalize()?;
-        self.link_object_files(&object_filepaths, output_filepath)
+        embed_missing_bitcode_to_object_file(
+            src_filepath,
+            object_filepath,
+            &err.to_string(),
+            &self.config().bitcode_path_prefix_maps(),
+        )?;
+
+        Ok(Some(0))
     }
 
     /// Generate bitcode file for one input file
@@ -168,7 +449,24 @@ pub trait CompilerWrapper {
         &self,
         src_filepath: P,
         bitcode_filepath: P,
+        language: Option<&str>,
     ) -> Result<Option<i32>, Error>
+    where
+        P: AsRef<Path>,
+    {
+        let args = self.generate_bitcode_file_command(src_filepath, bitcode_filepath, language);
+        let mode = CompileMode::BitcodeGeneration;
+
+        self.execute_command(&args, mode)
+    }
+
+    /// Command to generate the bitcode file for one input file
+    fn generate_bitcode_file_command<P>(
+        &self,
+        src_filepath: P,
+        bitcode_filepath: P,
+        language: Option<&str>,
+    ) -> Vec<String>
     where
         P: AsRef<Path>,
     {
@@ -179,7 +477,7 @@ pub trait CompilerWrapper {
         let mut args = vec![String::from(compiler_filepath.to_string_lossy())];
         args.extend(self.args().compile_args().iter().cloned());
         // Add bitcode generation flags
-        if let Some(bitcode_generation_flags) = rllvm_config().bitcode_generation_flags() {
+        if let Some(bitcode_generation_flags) = self.config().bitcode_generation_flags() {
             args.extend(bitcode_generation_flags.iter().cloned());
         }
         args.extend_from_slice(&[
@@ -187,20 +485,77 @@ pub trait CompilerWrapper {
             "-c".to_string(),
             "-o".to_string(),
             String::from(bitcode_filepath.to_string_lossy()),
-            String::from(src_filepath.to_string_lossy()),
         ]);
+        args.extend(language_args(language));
+        args.push(String::from(src_filepath.to_string_lossy()));
 
-        let mode = CompileMode::BitcodeGeneration;
+        args
+    }
+
+    /// Lower the bitcode file to the object file, with the same codegen flags
+    fn lower_bitcode_file<P>(
+        &self,
+        bitcode_filepath: P,
+        object_filepath: P,
+    ) -> Result<Option<i32>, Error>
+    where
+        P: AsRef<Path>,
+    {
+        let args = self.lower_bitcode_file_command(bitcode_filepath, object_filepath);
+        let mode = CompileMode::BitcodeLowering;
 
         self.execute_command(&args, mode)
     }
 
+    /// Command to lower the bitcode file to the object file
+    fn lower_bitcode_file_command<P>(&self, bitcode_filepath: P, object_filepath: P) -> Vec<String>
+    where
+        P: AsRef<Path>,
+    {
+        let bitcode_filepath = bitcode_filepath.as_ref();
+        let object_filepath = object_filepath.as_ref();
+        let wrapped_compiler = self.wrapped_compiler();
+
+        let mut args = vec![String::from(wrapped_compiler.to_string_lossy())];
+        args.extend(self.args().compile_args().iter().cloned());
+        args.extend_from_slice(&[
+            // Preprocessor flags are unused for bitcode inputs
+            "-Qunused-arguments".to_string(),
+            // The bitcode has been optimized; only run the code generator
+            "-Xclang".to_string(),
+            "-disable-llvm-passes".to_string(),
+            "-c".to_string(),
+            "-o".to_string(),
+            String::from(object_filepath.to_string_lossy()),
+            String::from(bitcode_filepath.to_string_lossy()),
+        ]);
+
+        args
+    }
+
     /// Execute the command and build the object file
     fn build_object_file<P>(
         &self,
         src_filepath: P,
         object_filepath: P,
+        language: Option<&str>,
     ) -> Result<Option<i32>, Error>
+    where
+        P: AsRef<Path>,
+    {
+        let args = self.build_object_file_command(src_filepath, object_filepath, language);
+        let mode = CompileMode::Compiling;
+
+        self.execute_command(&args, mode)
+    }
+
+    /// Command to build the object file for one input file
+    fn build_object_file_command<P>(
+        &self,
+        src_filepath: P,
+        object_filepath: P,
+        language: Option<&str>,
+    ) -> Vec<String>
     where
         P: AsRef<Path>,
     {
@@ -210,54 +565,58 @@ pub trait CompilerWrapper {
 
         let mut args = vec![String::from(wrapped_compiler.to_string_lossy())];
         args.extend(self.args().compile_args().iter().cloned());
+        // The object file is the output of the user's command, so it also
+        // writes the dependency files
+        args.extend(self.args().dependency_args().iter().cloned());
         args.extend_from_slice(&[
             "-c".to_string(),
             "-o".to_string(),
             String::from(object_filepath.to_string_lossy()),
-            String::from(src_filepath.to_string_lossy()),
         ]);
+        args.extend(language_args(language));
+        args.push(String::from(src_filepath.to_string_lossy()));
 
-        let mode = CompileMode::Compiling;
+        args
+    }
+
+    /// Link the object files of the input files, in place of the input files
+    fn link_object_files<P>(&self, object_filepaths: &[P]) -> Result<Option<i32>, Error>
+    where
+        P: AsRef<Path>,
+    {
+        let args = self.link_object_files_command(object_filepaths)?;
+        let mode = CompileMode::Linking;
 
         self.execute_command(&args, mode)
     }
 
-    fn link_object_files<P>(
-        &self,
-        object_filepaths: &[P],
-        output_filepath: P,
-    ) -> Result<Option<i32>, Error>
+    /// Command to link the object files of the input files
+    fn link_object_files_command<P>(&self, object_filepaths: &[P]) -> Result<Vec<String>, Error>
     where
         P: AsRef<Path>,
     {
-        let output_filepath = output_filepath.as_ref();
         let wrapped_compiler = self.wrapped_compiler();
 
         let mut args = vec![String::from(wrapped_compiler.to_string_lossy())];
         if self.args().is_lto() {
             // Add LTO LDFLAGS
-            if let Some(lto_ldflags) = rllvm_config().lto_ldflags() {
+            if let Some(lto_ldflags) = self.config().lto_ldflags() {
                 args.extend(lto_ldflags.iter().cloned());
             }
         }
-        // Link arguments
-        args.extend(self.args().link_args().iter().cloned());
-        // Output
-        args.extend_from_slice(&[
-            "-o".to_string(),
-            String::from(output_filepath.to_string_lossy()),
-        ]);
-        // Input object files
-        args.extend(
-            object_filepaths
-                .iter()
-                .map(|x| String::from(x.as_ref().to_string_lossy())),
-        );
+        // Compile flags are unused for object files
+        args.push("-Qunused-arguments".to_string());
+        // Given arguments, with input files replaced by object files
+        args.extend(self.args().object_link_args(object_filepaths)?);
 
-        // Mode
-        let mode = CompileMode::Linking;
+        // Remove forbidden flags
+        if !self.args().forbidden_flags().is_empty() {
+            let forbidden_flags_set: HashSet<&String> =
+                HashSet::from_iter(self.args().forbidden_flags().iter());
+            args.retain(|x| !forbidden_flags_set.contains(x));
+        }
 
-        self.execute_command(&args, mode)
+        Ok(args)
     }
 }
 
@@ -283,4 +642,16 @@ pub trait CompilerWrapperBuilder {
 
     /// Set the silence flag
     fn silence(self, value: bool) -> Self;
+
+    /// Set how the bitcode is embedded into object files
+    fn bitcode_embedding(self, bitcode_embedding: BitcodeEmbedding) -> Self;
+
+    /// Set whether unknown flags are appended to the unknown-flag log of the
+    /// configuration, if any
+    fn record_unknown_flags(self, value: bool) -> Self;
+
+    /// Set the configuration, instead of the global configuration
+    fn config<C>(self, config: C) -> Self
+    where
+        C: Into<Arc<RLLVMConfig>>;
 }
@@ -1,32 +1,381 @@
 use std::{
-    env, fs,
+    collections::BTreeMap,
+    env, fmt, fs,
     path::{Path, PathBuf},
-    sync::OnceLock,
+    sync::{Arc, OnceLock},
 };
 
+use clap::ValueEnum;
 use log::Level;
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    arg_parser::flag_rule_patterns,
     constants::{
-        DEFAULT_CONF_FILEPATH_UNDER_HOME, DEFAULT_RLLVM_CONF_FILEPATH_ENV_NAME, HOME_ENV_NAME,
+        CONF_ENV_NAME_PREFIX, DEFAULT_CONF_FILEPATH_UNDER_HOME,
+        DEFAULT_RLLVM_CONF_FILEPATH_ENV_NAME, GLLVM_AR_NAME_ENV_NAME, GLLVM_BC_STORE_ENV_NAME,
+        GLLVM_BITCODE_GENERATION_FLAGS_ENV_NAME, GLLVM_CC_NAME_ENV_NAME,
+        GLLVM_COMPILER_PATH_ENV_NAME, GLLVM_CONFIGURE_ONLY_ENV_NAME, GLLVM_CXX_NAME_ENV_NAME,
+        GLLVM_LINK_NAME_ENV_NAME, HOME_ENV_NAME, PROJECT_CONF_FILENAME, SYSTEM_CONF_FILEPATH,
     },
-    utils::{execute_llvm_config, find_llvm_config},
+    error::Error,
+    utils::{PathPrefixMap, execute_llvm_config, find_llvm_config},
 };
 
-#[cfg(not(test))]
+static RLLVM_CONFIG: OnceLock<Arc<RLLVMConfig>> = OnceLock::new();
+
+/// Obtain the global configuration, which is the default configuration of
+/// compiler wrappers
+///
+/// Unless a configuration has been installed by [`set_rllvm_config`], the
+/// configuration is loaded from the default configuration file (see
+/// [`RLLVMConfig::try_new`]) on first use.
+///
+/// # Panics
+///
+/// Panics if the configuration has not been installed and cannot be loaded.
 pub fn rllvm_config() -> &'static RLLVMConfig {
-    static RLLVM_CONFIG: OnceLock<RLLVMConfig> = OnceLock::new();
-    RLLVM_CONFIG.get_or_init(RLLVMConfig::new)
+    shared_rllvm_config()
 }
 
-#[cfg(test)]
-pub fn rllvm_config() -> &'static RLLVMConfig {
-    static RLLVM_CONFIG: OnceLock<RLLVMConfig> = OnceLock::new();
-    RLLVM_CONFIG.get_or_init(RLLVMConfig::default)
+/// Obtain the shared pointer to the global configuration (see
+/// [`rllvm_config`])
+pub fn shared_rllvm_config() -> &'static Arc<RLLVMConfig> {
+    RLLVM_CONFIG.get_or_init(|| {
+        #[cfg(not(test))]
+        let config = RLLVMConfig::try_new();
+        #[cfg(test)]
+        let config = RLLVMConfig::try_infer();
+
+        Arc::new(
+            config.unwrap_or_else(|err| panic!("Failed to load the rllvm configuration: {}", err)),
+        )
+    })
+}
+
+/// Install the given configuration as the global configuration
+///
+/// This fails if the global configuration has already been installed or
+/// loaded.
+pub fn set_rllvm_config<C>(config: C) -> Result<(), Error>
+where
+    C: Into<Arc<RLLVMConfig>>,
+{
+    RLLVM_CONFIG
+        .set(config.into())
+        .map_err(|_| Error::ConfigAlreadyInitialized)
+}
+
+/// How the bitcode is embedded into object files
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum BitcodeEmbedding {
+    /// Embed the filepath of the bitcode file
+    #[default]
+    Filepath,
+    /// Embed the contents of the bitcode file
+    Contents,
+    /// Embed the zlib-compressed contents of the bitcode file
+    CompressedContents,
+}
+
+/// What to do if the bitcode of a source file cannot be generated
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum BitcodeGenerationPolicy {
+    /// Fail the build
+    #[default]
+    Strict,
+    /// Continue the native build, and record the source file without bitcode
+    /// in the object file
+    BestEffort,
+}
+
+/// How a compiler flag is passed on
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum FlagRole {
+    /// Pass the flag to compile commands
+    Compile,
+    /// Pass the flag to link commands
+    Link,
+    /// Pass the flag to both compile and link commands
+    Both,
+    /// Skip the bitcode generation if the flag is given
+    SkipBitcode,
+    /// Remove the flag from the wrapped compiler command
+    Forbidden,
+}
+
+/// A user-defined rule to classify compiler flags, which takes precedence over
+/// the built-in ones
+///
+/// Either `flag` or `pattern` is given, e.g., `{ flag = "-mfoo", arity = 1,
+/// role = "compile" }`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct FlagRule {
+    /// The flag, which is matched exactly
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    flag: Option<String>,
+
+    /// The regular expression that matches the flag
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pattern: Option<String>,
+
+    /// The number of parameters following the flag (Default: 0)
+    #[serde(default)]
+    arity: usize,
+
+    /// How the flag and its parameters are passed on
+    role: FlagRole,
+}
+
+impl FlagRule {
+    pub fn flag(&self) -> Option<&str> {
+        self.flag.as_deref()
+    }
+
+    pub fn pattern(&self) -> Option<&str> {
+        self.pattern.as_deref()
+    }
+
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    pub fn role(&self) -> FlagRole {
+        self.role
+    }
+}
+
+/// Where the value of a configuration key comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigSource {
+    /// Inferred from the LLVM installation
+    Inferred,
+    /// A configuration file
+    File(PathBuf),
+    /// An environment variable
+    Env(String),
+    /// A command-line option
+    CommandLine,
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Inferred => write!(f, "inferred"),
+            Self::File(filepath) => write!(f, "file: {}", filepath.display()),
+            Self::Env(name) => write!(f, "env: {}", name),
+            Self::CommandLine => write!(f, "command line"),
+        }
+    }
+}
+
+/// Kind of the value of a configuration key, for parsing environment variables
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConfigValueKind {
+    String,
+    Bool,
+    Integer,
+    /// Shell-quoted words, e.g., "-flto -fwhole-program-vtables"
+    List,
+    /// An inline TOML value, e.g., `[{ flag = "-mfoo", role = "compile" }]`
+    Toml,
+}
+
+/// All configuration keys
+const CONFIG_KEYS: &[(&str, ConfigValueKind)] = &[
+    ("llvm_config_filepath", ConfigValueKind::String),
+    ("clang_filepath", ConfigValueKind::String),
+    ("clangxx_filepath", ConfigValueKind::String),
+    ("llvm_ar_filepath", ConfigValueKind::String),
+    ("llvm_link_filepath", ConfigValueKind::String),
+    ("llvm_objcopy_filepath", ConfigValueKind::String),
+    ("bitcode_store_path", ConfigValueKind::String),
+    ("llvm_link_flags", ConfigValueKind::List),
+    ("lto_ldflags", ConfigValueKind::List),
+    ("bitcode_generation_flags", ConfigValueKind::List),
+    ("is_configure_only", ConfigValueKind::Bool),
+    ("bitcode_path_prefix_map", ConfigValueKind::List),
+    ("is_single_compile", ConfigValueKind::Bool),
+    ("jobs", ConfigValueKind::Integer),
+    ("bitcode_embedding", ConfigValueKind::String),
+    ("bitcode_generation_policy", ConfigValueKind::String),
+    ("log_level", ConfigValueKind::Integer),
+    ("flag_rules", ConfigValueKind::Toml),
+    ("unknown_flags_log", ConfigValueKind::String),
+];
+
+/// Configuration keys without defaults
+const REQUIRED_CONFIG_KEYS: &[&str] = &[
+    "llvm_config_filepath",
+    "clang_filepath",
+    "clangxx_filepath",
+    "llvm_ar_filepath",
+    "llvm_link_filepath",
+    "llvm_objcopy_filepath",
+];
+
+/// Obtain the name of the environment variable that overrides the
+/// configuration key, i.e., `RLLVM_` followed by the upper-case key without
+/// the `is_` prefix, e.g., `RLLVM_CONFIGURE_ONLY` for `is_configure_only`
+pub fn config_key_env_name(key: &str) -> String {
+    format!(
+        "{}{}",
+        CONF_ENV_NAME_PREFIX,
+        key.strip_prefix("is_").unwrap_or(key).to_uppercase()
+    )
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Read the configuration keys from the configuration file
+fn read_config_layer(config_filepath: &Path) -> Result<toml::Table, Error> {
+    let contents = fs::read_to_string(config_filepath)?;
+    contents.parse().map_err(|err: toml::de::Error| {
+        Error::InvalidConfig(format!("{:?}: {}", config_filepath, err))
+    })
+}
+
+/// Read the configuration keys from the environment variables, one layer per
+/// variable, in the order of configuration keys
+fn env_config_layers<I>(vars: I) -> Result<Vec<(ConfigSource, toml::Table)>, Error>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let vars: BTreeMap<String, String> = vars.into_iter().collect();
+
+    let mut layers = vec![];
+    for (key, kind) in CONFIG_KEYS {
+        let env_name = config_key_env_name(key);
+        let Some(var) = vars.get(&env_name) else {
+            continue;
+        };
+
+        let value = parse_config_value(*kind, var).ok_or_else(|| {
+            Error::InvalidConfig(format!("Invalid value of `{}`: {:?}", env_name, var))
+        })?;
+
+        let mut layer = toml::Table::new();
+        layer.insert(key.to_string(), value);
+        layers.push((ConfigSource::Env(env_name), layer));
+    }
+
+    Ok(layers)
+}
+
+/// LLVM tools configurable by gllvm environment variables, i.e., the
+/// configuration key, the environment variable of the tool name, and the
+/// default tool name
+///
+/// gllvm does not use `llvm-config` and `llvm-objcopy`, which are therefore
+/// left to other layers, as `LLVM_COMPILER_PATH` may not contain them.
+const GLLVM_TOOLS: &[(&str, &str, &str)] = &[
+    ("clang_filepath", GLLVM_CC_NAME_ENV_NAME, "clang"),
+    ("clangxx_filepath", GLLVM_CXX_NAME_ENV_NAME, "clang++"),
+    ("llvm_ar_filepath", GLLVM_AR_NAME_ENV_NAME, "llvm-ar"),
+    ("llvm_link_filepath", GLLVM_LINK_NAME_ENV_NAME, "llvm-link"),
+];
+
+/// Read the configuration keys from the environment variables of gllvm and
+/// wllvm, one layer per configuration key
+///
+/// Tools are found under `LLVM_COMPILER_PATH`, with names from `LLVM_CC_NAME`,
+/// `LLVM_CXX_NAME`, `LLVM_AR_NAME` and `LLVM_LINK_NAME`. Without
+/// `LLVM_COMPILER_PATH`, only tools with given names are configured, and they
+/// are searched in `PATH`. As in gllvm, empty variables are ignored, and any
+/// non-empty `WLLVM_CONFIGURE_ONLY` enables the configure-only mode.
+fn gllvm_env_config_layers<I>(vars: I) -> Result<Vec<(ConfigSource, toml::Table)>, Error>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let vars: BTreeMap<String, String> = vars
+        .into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        .collect();
+    let compiler_path = vars.get(GLLVM_COMPILER_PATH_ENV_NAME).map(Path::new);
+
+    let mut layers = vec![];
+    let mut push_layer = |env_name: &str, key: &str, value: toml::Value| {
+        let mut layer = toml::Table::new();
+        layer.insert(key.to_string(), value);
+        layers.push((ConfigSource::Env(env_name.to_string()), layer));
+    };
+
+    for (key, name_env_name, default_name) in GLLVM_TOOLS {
+        let name = vars.get(*name_env_name);
+        let filepath = match (compiler_path, name) {
+            (Some(compiler_path), _) => {
+                compiler_path.join(name.map_or(*default_name, String::as_str))
+            }
+            (None, Some(name)) => which::which(name).unwrap_or_else(|_| PathBuf::from(name)),
+            (None, None) => continue,
+        };
+        let env_name = match name {
+            Some(_) => name_env_name,
+            None => GLLVM_COMPILER_PATH_ENV_NAME,
+        };
+        push_layer(
+            env_name,
+            key,
+            toml::Value::String(filepath.to_string_lossy().into_owned()),
+        );
+    }
+
+    if vars.contains_key(GLLVM_CONFIGURE_ONLY_ENV_NAME) {
+        push_layer(
+            GLLVM_CONFIGURE_ONLY_ENV_NAME,
+            "is_configure_only",
+            toml::Value::Boolean(true),
+        );
+    }
+
+    if let Some(bc_store) = vars.get(GLLVM_BC_STORE_ENV_NAME) {
+        push_layer(
+            GLLVM_BC_STORE_ENV_NAME,
+            "bitcode_store_path",
+            toml::Value::String(bc_store.clone()),
+        );
+    }
+
+    if let Some(flags) = vars.get(GLLVM_BITCODE_GENERATION_FLAGS_ENV_NAME) {
+        let value = parse_config_value(ConfigValueKind::List, flags).ok_or_else(|| {
+            Error::InvalidConfig(format!(
+                "Invalid value of `{}`: {:?}",
+                GLLVM_BITCODE_GENERATION_FLAGS_ENV_NAME, flags
+            ))
+        })?;
+        push_layer(
+            GLLVM_BITCODE_GENERATION_FLAGS_ENV_NAME,
+            "bitcode_generation_flags",
+            value,
+        );
+    }
+
+    Ok(layers)
+}
+
+/// Parse the textual value of a configuration key, e.g., from an environment
+/// variable or a command-line option
+fn parse_config_value(kind: ConfigValueKind, value: &str) -> Option<toml::Value> {
+    match kind {
+        ConfigValueKind::String => Some(toml::Value::String(value.to_string())),
+        ConfigValueKind::Bool => match value.trim().to_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Some(toml::Value::Boolean(true)),
+            "" | "0" | "false" | "no" | "off" => Some(toml::Value::Boolean(false)),
+            _ => None,
+        },
+        ConfigValueKind::Integer => value.trim().parse().ok().map(toml::Value::Integer),
+        ConfigValueKind::List => shlex::split(value)
+            .map(|words| toml::Value::Array(words.into_iter().map(toml::Value::String).collect())),
+        ConfigValueKind::Toml => format!("value = {}", value)
+            .parse::<toml::Table>()
+            .ok()?
+            .remove("value"),
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RLLVMConfig {
     /// The absolute filepath of `llvm-config`
     llvm_config_filepath: PathBuf,
@@ -61,8 +410,40 @@ pub struct RLLVMConfig {
     /// The configure only mode, which skips the bitcode generation (Default: false)
     is_configure_only: Option<bool>,
 
+    /// Path prefix maps in the form of "OLD=NEW", applied to bitcode filepaths
+    /// before embedding them, e.g., "/home/user/build=/build"
+    bitcode_path_prefix_map: Option<Vec<String>>,
+
+    /// The single-compile mode, which lowers object files from the generated
+    /// bitcode instead of compiling the sources twice (Default: false)
+    is_single_compile: Option<bool>,
+
+    /// The maximum number of input files processed concurrently, if the GNU
+    /// make jobserver is unavailable (Default: the number of CPUs)
+    jobs: Option<usize>,
+
+    /// What to embed into object files: "filepath", "contents" or
+    /// "compressed-contents" (Default: "filepath")
+    bitcode_embedding: Option<BitcodeEmbedding>,
+
+    /// What to do if the bitcode of a source file cannot be generated:
+    /// "strict" or "best-effort" (Default: "strict")
+    bitcode_generation_policy: Option<BitcodeGenerationPolicy>,
+
     /// Log level (Default: 0, print nothing)
     log_level: Option<u8>,
+
+    /// User-defined rules to classify compiler flags, which take precedence
+    /// over the built-in ones
+    flag_rules: Option<Vec<FlagRule>>,
+
+    /// The absolute filepath of the log, to which unrecognized compiler flags
+    /// are appended
+    unknown_flags_log: Option<PathBuf>,
+
+    /// Where the value of each configuration key comes from
+    #[serde(skip)]
+    sources: BTreeMap<String, ConfigSource>,
 }
 
 impl RLLVMConfig {
@@ -110,6 +491,44 @@ impl RLLVMConfig {
         self.is_configure_only.unwrap_or_default()
     }
 
+    pub fn is_single_compile(&self) -> bool {
+        self.is_single_compile.unwrap_or_default()
+    }
+
+    pub fn jobs(&self) -> usize {
+        self.jobs.unwrap_or_else(|| {
+            std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get)
+        })
+    }
+
+    pub fn bitcode_path_prefix_maps(&self) -> Vec<PathPrefixMap> {
+        self.bitcode_path_prefix_map
+            .iter()
+            .flatten()
+            .filter_map(|x| {
+                x.parse()
+                    .inspect_err(|err| log::warn!("Ignore the path prefix map: {}", err))
+                    .ok()
+            })
+            .collect()
+    }
+
+    pub fn bitcode_embedding(&self) -> BitcodeEmbedding {
+        self.bitcode_embedding.unwrap_or_default()
+    }
+
+    pub fn bitcode_generation_policy(&self) -> BitcodeGenerationPolicy {
+        self.bitcode_generation_policy.unwrap_or_default()
+    }
+
+    pub fn flag_rules(&self) -> &[FlagRule] {
+        self.flag_rules.as_deref().unwrap_or_default()
+    }
+
+    pub fn unknown_flags_log(&self) -> Option<&PathBuf> {
+        self.unknown_flags_log.as_ref()
+    }
+
     pub fn log_level(&self) -> Level {
         Level::iter()
             .nth(self.log_level.unwrap_or_default() as usize)
@@ -118,8 +537,10 @@ impl RLLVMConfig {
 }
 
 impl RLLVMConfig {
-    pub fn new() -> Self {
-        let config_filepath = env::var(DEFAULT_RLLVM_CONF_FILEPATH_ENV_NAME).map_or_else(
+    /// Obtain the filepath of the user configuration file, i.e.,
+    /// `$RLLVM_CONFIG`, or `~/.rllvm/config.toml` by default
+    pub fn default_filepath() -> PathBuf {
+        env::var(DEFAULT_RLLVM_CONF_FILEPATH_ENV_NAME).map_or_else(
             |_| {
                 // Default config file
                 PathBuf::from(env::var(HOME_ENV_NAME).unwrap_or("".into()))
@@ -129,78 +550,248 @@ impl RLLVMConfig {
                 // User-defined config file
                 PathBuf::from(x)
             },
-        );
-        Self::load_path(config_filepath)
+        )
+    }
+
+    /// Find the project configuration file, i.e., the nearest `.rllvm.toml` in
+    /// the given directory or its ancestors
+    pub fn find_project_filepath<P>(dir: P) -> Option<PathBuf>
+    where
+        P: AsRef<Path>,
+    {
+        dir.as_ref()
+            .ancestors()
+            .map(|dir| dir.join(PROJECT_CONF_FILENAME))
+            .find(|filepath| filepath.is_file())
+    }
+
+    /// Load the configuration from all layers, where later layers override
+    /// earlier ones:
+    ///
+    /// 1. The system configuration file, i.e., `/etc/rllvm/config.toml`
+    /// 2. The user configuration file (see [`RLLVMConfig::default_filepath`])
+    /// 3. The project configuration file (see
+    ///    [`RLLVMConfig::find_project_filepath`]), searched from the current
+    ///    working directory
+    /// 4. gllvm and wllvm environment variables, e.g., `LLVM_COMPILER_PATH`
+    ///    and `WLLVM_BC_STORE`
+    /// 5. `RLLVM_*` environment variables, e.g., `RLLVM_BITCODE_STORE_PATH`
+    ///    (see [`config_key_env_name`])
+    ///
+    /// Required keys that no layer provides are inferred (see
+    /// [`RLLVMConfig::try_infer`]). If no configuration file exists, the
+    /// inferred configuration is saved to the user configuration file.
+    pub fn try_new() -> Result<Self, Error> {
+        Self::try_new_with_overrides(Vec::<(&str, &str)>::new())
+    }
+
+    /// Load the configuration from all layers (see [`RLLVMConfig::try_new`]),
+    /// and override configuration keys with the given textual values, e.g.,
+    /// from command-line options
+    ///
+    /// Values are parsed in the same way as environment variables.
+    pub fn try_new_with_overrides<I, K, V>(overrides: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let user_filepath = Self::default_filepath();
+        let project_filepath = env::current_dir()
+            .ok()
+            .and_then(Self::find_project_filepath);
+
+        let mut layers = vec![];
+        for filepath in [
+            Some(PathBuf::from(SYSTEM_CONF_FILEPATH)),
+            Some(user_filepath.clone()),
+            project_filepath,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if filepath.is_file() {
+                let layer = read_config_layer(&filepath)?;
+                layers.push((ConfigSource::File(filepath), layer));
+            }
+        }
+        layers.extend(gllvm_env_config_layers(env::vars())?);
+        layers.extend(env_config_layers(env::vars())?);
+
+        let mut override_layer = toml::Table::new();
+        for (key, value) in overrides {
+            let (key, value) = (key.as_ref(), value.as_ref());
+            let (_, kind) = CONFIG_KEYS
+                .iter()
+                .find(|(name, _)| *name == key)
+                .ok_or_else(|| {
+                    Error::InvalidConfig(format!("Unknown configuration key: {}", key))
+                })?;
+            let value = parse_config_value(*kind, value).ok_or_else(|| {
+                Error::InvalidConfig(format!("Invalid value of `{}`: {:?}", key, value))
+            })?;
+            override_layer.insert(key.to_string(), value);
+        }
+        if !override_layer.is_empty() {
+            layers.push((ConfigSource::CommandLine, override_layer));
+        }
+
+        Self::try_from_layers(layers, &user_filepath)
     }
 
-    fn load_path<P>(config_filepath: P) -> Self
+    /// Load the configuration from the given file only
+    ///
+    /// If the file does not exist, the configuration is inferred (see
+    /// [`RLLVMConfig::try_infer`]) and saved to the file.
+    pub fn try_load<P>(config_filepath: P) -> Result<Self, Error>
     where
-        P: AsRef<Path> + std::fmt::Debug,
+        P: AsRef<Path>,
     {
         let config_filepath = config_filepath.as_ref();
-        match confy::load_path::<RLLVMConfig>(config_filepath) {
-            Ok(mut config) => {
-                if let Some(bitcode_store_path) = &config.bitcode_store_path {
-                    // Check if the bitcode store path is absolute or not
-                    if !bitcode_store_path.is_absolute() {
-                        // Not absolute
-                        log::warn!(
-                            "Ignore the bitcode store path, as it is not absolute: {:?}",
-                            bitcode_store_path
-                        );
-                        config.bitcode_store_path = None;
-                    } else {
-                        // Further check if the directory exists
-                        if !bitcode_store_path.exists() {
-                            // Not exist, then create it
-                            log::info!(
-                                "Create the directory for the bitcode store: {:?}",
-                                bitcode_store_path
-                            );
-                            if let Err(err) = fs::create_dir_all(bitcode_store_path) {
-                                log::error!(
-                                    "Failed to create the bitcode store directory: err={}",
-                                    err
-                                );
-                                std::process::exit(1);
-                            }
-                        } else {
-                            // Finally, check if this is a directory
-                            if !bitcode_store_path.is_dir() {
-                                // Not a directory
-                                log::warn!(
-                                    "Ignore the bitcode store path, as it is not a directory: {:?}",
-                                    bitcode_store_path
-                                );
-                                config.bitcode_store_path = None;
-                            }
-                        }
-                    }
+        let mut layers = vec![];
+        if config_filepath.exists() {
+            let layer = read_config_layer(config_filepath)?;
+            layers.push((ConfigSource::File(config_filepath.to_path_buf()), layer));
+        }
+
+        Self::try_from_layers(layers, config_filepath)
+    }
+
+    /// Merge the layers of configuration keys, where later layers override
+    /// earlier ones
+    fn try_from_layers(
+        layers: Vec<(ConfigSource, toml::Table)>,
+        default_filepath: &Path,
+    ) -> Result<Self, Error> {
+        let has_config_file = layers
+            .iter()
+            .any(|(source, _)| matches!(source, ConfigSource::File(_)));
+
+        let mut table = toml::Table::new();
+        let mut sources = BTreeMap::new();
+        for (source, layer) in layers {
+            for (key, value) in layer {
+                if !CONFIG_KEYS.iter().any(|(name, _)| *name == key) {
+                    log::warn!("Ignore the unknown configuration key: {} ({})", key, source);
+                    continue;
                 }
+                table.insert(key.clone(), value);
+                sources.insert(key, source.clone());
+            }
+        }
 
-                config
+        // Infer missing required keys
+        if REQUIRED_CONFIG_KEYS
+            .iter()
+            .any(|key| !table.contains_key(*key))
+        {
+            let inferred_config = Self::try_infer()?;
+            if !has_config_file {
+                // Saving is only a cache for later runs, so that read-only home
+                // directories do not fail the build
+                log::info!("Save the configuration: {:?}", default_filepath);
+                if let Err(err) = confy::store_path(default_filepath, &inferred_config) {
+                    log::warn!(
+                        "Failed to save the configuration: filepath={:?}, err={}",
+                        default_filepath,
+                        err
+                    );
+                }
             }
-            Err(err) => {
-                log::error!(
-                    "Failed to load configuration: config_filepath={:?}, err={}",
-                    config_filepath,
-                    err
+
+            let inferred_table = toml::Table::try_from(&inferred_config)
+                .map_err(|err| Error::InvalidConfig(err.to_string()))?;
+            for key in REQUIRED_CONFIG_KEYS {
+                if !table.contains_key(*key)
+                    && let Some(value) = inferred_table.get(*key)
+                {
+                    table.insert(key.to_string(), value.clone());
+                    sources.insert(key.to_string(), ConfigSource::Inferred);
+                }
+            }
+        }
+
+        let mut config: Self = table
+            .try_into()
+            .map_err(|err: toml::de::Error| Error::InvalidConfig(err.message().to_string()))?;
+        config.sources = sources;
+
+        // Check user-defined flag rules
+        flag_rule_patterns(config.flag_rules())?;
+
+        // Every compiler invocation appends to the same log, regardless of
+        // its working directory
+        if let Some(unknown_flags_log) = &config.unknown_flags_log
+            && !unknown_flags_log.is_absolute()
+        {
+            return Err(Error::InvalidConfig(format!(
+                "The unknown-flag log is not absolute: {:?}",
+                unknown_flags_log
+            )));
+        }
+
+        if let Some(bitcode_store_path) = &config.bitcode_store_path {
+            // Check if the bitcode store path is absolute or not
+            if !bitcode_store_path.is_absolute() {
+                // Not absolute
+                log::warn!(
+                    "Ignore the bitcode store path, as it is not absolute: {:?}",
+                    bitcode_store_path
+                );
+                config.bitcode_store_path = None;
+            } else if !bitcode_store_path.exists() {
+                // Not exist, then create it
+                log::info!(
+                    "Create the directory for the bitcode store: {:?}",
+                    bitcode_store_path
                 );
-                std::process::exit(1);
+                fs::create_dir_all(bitcode_store_path)?;
+            } else if !bitcode_store_path.is_dir() {
+                // Not a directory
+                log::warn!(
+                    "Ignore the bitcode store path, as it is not a directory: {:?}",
+                    bitcode_store_path
+                );
+                config.bitcode_store_path = None;
             }
         }
+
+        Ok(config)
+    }
+
+    /// Obtain where the value of the configuration key comes from, if the key
+    /// is set
+    pub fn source(&self, key: &str) -> Option<&ConfigSource> {
+        self.sources.get(key)
     }
-}
 
-impl Default for RLLVMConfig {
-    fn default() -> Self {
+    /// Dump the effective configuration in TOML, annotating where each value
+    /// comes from
+    pub fn to_annotated_toml(&self) -> Result<String, Error> {
+        let table =
+            toml::Table::try_from(self).map_err(|err| Error::InvalidConfig(err.to_string()))?;
+
+        let mut lines = vec![];
+        for (key, _) in CONFIG_KEYS {
+            match (table.get(*key), self.source(key)) {
+                (Some(value), Some(source)) => {
+                    lines.push(format!("{} = {} # {}", key, value, source));
+                }
+                (Some(value), None) => lines.push(format!("{} = {}", key, value)),
+                (None, _) => lines.push(format!("# {} is not set", key)),
+            }
+        }
+
+        Ok(lines.join("\n"))
+    }
+
+    /// Infer the configuration from the LLVM installation found by
+    /// `llvm-config`
+    pub fn try_infer() -> Result<Self, Error> {
         log::info!("Infer rllvm configurations ...");
 
         // Find `llvm-config`
-        let llvm_config_filepath = find_llvm_config().unwrap_or_else(|err| {
-            log::error!("Failed to find `llvm-config`: err={:?}", err);
-            std::process::exit(1);
-        });
+        let llvm_config_filepath = find_llvm_config()?;
         log::info!("- llvm-config: {:?}", llvm_config_filepath);
 
         // Obtain LLVM version
@@ -209,13 +800,7 @@ impl Default for RLLVMConfig {
             Err(err) => log::warn!("- LLVM version: (unknown, err={:?})", err),
         }
 
-        let llvm_bindir = execute_llvm_config(&llvm_config_filepath, &["--bindir"]).map_or_else(
-            |err| {
-                log::error!("Failed to execute `llvm-config --bindir`: {:?}", err);
-                std::process::exit(1);
-            },
-            PathBuf::from,
-        );
+        let llvm_bindir = PathBuf::from(execute_llvm_config(&llvm_config_filepath, &["--bindir"])?);
 
         // Find `clang`
         let clang_filepath = llvm_bindir.join("clang");
@@ -241,12 +826,14 @@ impl Default for RLLVMConfig {
         ];
         for llvm_bin_filepath in llvm_bin_filepaths {
             if !llvm_bin_filepath.exists() {
-                log::error!("Failed to find `{:?}`", llvm_bin_filepath);
-                std::process::exit(1);
+                return Err(Error::MissingFile(format!(
+                    "Failed to find `{}`",
+                    llvm_bin_filepath.display()
+                )));
             }
         }
 
-        Self {
+        Ok(Self {
             llvm_config_filepath,
             clang_filepath,
             clangxx_filepath,
@@ -258,7 +845,224 @@ impl Default for RLLVMConfig {
             lto_ldflags: None,
             bitcode_generation_flags: None,
             is_configure_only: None,
+            bitcode_path_prefix_map: None,
+            is_single_compile: None,
+            jobs: None,
+            bitcode_embedding: None,
+            bitcode_generation_policy: None,
             log_level: None,
-        }
+            flag_rules: None,
+            unknown_flags_log: None,
+            sources: BTreeMap::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_try_load() {
+        let config_filepath = Path::new("/tmp/rllvm_test_config.toml");
+        fs::write(
+            config_filepath,
+            r#"
+llvm_config_filepath = '/usr/bin/llvm-config'
+clang_filepath = '/usr/bin/clang'
+clangxx_filepath = '/usr/bin/clang++'
+llvm_ar_filepath = '/usr/bin/llvm-ar'
+llvm_link_filepath = '/usr/bin/llvm-link'
+llvm_objcopy_filepath = '/usr/bin/llvm-objcopy'
+bitcode_store_path = 'relative/store'
+bitcode_embedding = 'compressed-contents'
+"#,
+        )
+        .expect("Failed to write the config file");
+        let config = RLLVMConfig::try_load(config_filepath).expect("Failed to load the config");
+        assert_eq!(config.clang_filepath(), Path::new("/usr/bin/clang"));
+        assert_eq!(
+            config.bitcode_embedding(),
+            BitcodeEmbedding::CompressedContents
+        );
+        // Relative bitcode store paths are ignored
+        assert!(config.bitcode_store_path().is_none());
+
+        fs::write(
+            config_filepath,
+            r#"
+llvm_config_filepath = '/usr/bin/llvm-config'
+clang_filepath = '/usr/bin/clang'
+clangxx_filepath = '/usr/bin/clang++'
+llvm_ar_filepath = '/usr/bin/llvm-ar'
+llvm_link_filepath = '/usr/bin/llvm-link'
+llvm_objcopy_filepath = '/usr/bin/llvm-objcopy'
+unknown_flags_log = 'unknown_flags.log'
+"#,
+        )
+        .expect("Failed to write the config file");
+        assert!(matches!(
+            RLLVMConfig::try_load(config_filepath),
+            Err(Error::InvalidConfig(message)) if message.contains("unknown_flags.log")
+        ));
+
+        fs::write(config_filepath, "clang_filepath = [").expect("Failed to write the config file");
+        assert!(matches!(
+            RLLVMConfig::try_load(config_filepath),
+            Err(Error::InvalidConfig(_))
+        ));
+
+        // Clean
+        fs::remove_file(config_filepath).expect("Failed to delete the config file");
+    }
+
+    #[test]
+    fn test_config_layers() {
+        let file_layer: toml::Table = r#"
+llvm_config_filepath = '/usr/bin/llvm-config'
+clang_filepath = '/usr/bin/clang'
+clangxx_filepath = '/usr/bin/clang++'
+llvm_ar_filepath = '/usr/bin/llvm-ar'
+llvm_link_filepath = '/usr/bin/llvm-link'
+llvm_objcopy_filepath = '/usr/bin/llvm-objcopy'
+bitcode_generation_flags = ['-O0']
+jobs = 2
+"#
+        .parse()
+        .unwrap();
+        let project_layer: toml::Table = "clang_filepath = '/opt/llvm/bin/clang'".parse().unwrap();
+        let env_layers = env_config_layers([
+            ("RLLVM_CONFIGURE_ONLY".to_string(), "1".to_string()),
+            (
+                "RLLVM_BITCODE_GENERATION_FLAGS".to_string(),
+                "-flto '-DNAME=a b'".to_string(),
+            ),
+            ("UNRELATED".to_string(), "1".to_string()),
+        ])
+        .expect("Failed to read environment variables");
+        assert_eq!(env_layers.len(), 2);
+
+        let mut layers = vec![
+            (
+                ConfigSource::File("/etc/rllvm/config.toml".into()),
+                file_layer,
+            ),
+            (ConfigSource::File("/src/.rllvm.toml".into()), project_layer),
+        ];
+        layers.extend(env_layers);
+        let config = RLLVMConfig::try_from_layers(layers, Path::new("/nonexistent"))
+            .expect("Failed to merge layers");
+
+        assert_eq!(config.clang_filepath(), Path::new("/opt/llvm/bin/clang"));
+        assert_eq!(
+            config.source("clang_filepath"),
+            Some(&ConfigSource::File("/src/.rllvm.toml".into()))
+        );
+        assert_eq!(config.clangxx_filepath(), Path::new("/usr/bin/clang++"));
+        assert!(config.is_configure_only());
+        assert_eq!(
+            config.source("is_configure_only"),
+            Some(&ConfigSource::Env("RLLVM_CONFIGURE_ONLY".into()))
+        );
+        assert_eq!(
+            config.bitcode_generation_flags(),
+            Some(&vec!["-flto".to_string(), "-DNAME=a b".to_string()])
+        );
+        assert_eq!(config.jobs(), 2);
+        assert!(config.source("log_level").is_none());
+
+        let annotated = config
+            .to_annotated_toml()
+            .expect("Failed to dump the config");
+        assert!(annotated.contains("is_configure_only = true # env: RLLVM_CONFIGURE_ONLY"));
+        assert!(annotated.contains("# log_level is not set"));
+
+        // Invalid values
+        assert!(env_config_layers([("RLLVM_JOBS".to_string(), "many".to_string())]).is_err());
+    }
+
+    #[test]
+    fn test_gllvm_env_config_layers() {
+        let vars = |vars: &[(&str, &str)]| {
+            vars.iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<Vec<_>>()
+        };
+
+        let mut layers = gllvm_env_config_layers(vars(&[
+            ("LLVM_COMPILER_PATH", "/opt/llvm/bin"),
+            ("LLVM_CC_NAME", "clang-17"),
+            ("WLLVM_CONFIGURE_ONLY", "0"),
+            ("WLLVM_BC_STORE", "/tmp/bitcode_store"),
+            (
+                "LLVM_BITCODE_GENERATION_FLAGS",
+                "-flto -fwhole-program-vtables",
+            ),
+            ("LLVM_AR_NAME", ""),
+        ]))
+        .expect("Failed to read environment variables");
+        // Tools that gllvm does not use are left to other layers
+        assert!(layers.iter().all(|(_, layer)| {
+            !layer.contains_key("llvm_config_filepath")
+                && !layer.contains_key("llvm_objcopy_filepath")
+        }));
+        let config_filepath = PathBuf::from("/tmp/rllvm_test_gllvm.toml");
+        let file_layer: toml::Table = r#"
+llvm_config_filepath = '/usr/bin/llvm-config'
+llvm_objcopy_filepath = '/usr/bin/llvm-objcopy'
+"#
+        .parse()
+        .unwrap();
+        layers.insert(0, (ConfigSource::File(config_filepath), file_layer));
+        // `RLLVM_*` environment variables take precedence
+        layers.extend(
+            env_config_layers(vars(&[("RLLVM_BITCODE_STORE_PATH", "/tmp/rllvm_store")]))
+                .expect("Failed to read environment variables"),
+        );
+        let config = RLLVMConfig::try_from_layers(layers, Path::new("/nonexistent"))
+            .expect("Failed to merge layers");
+
+        assert_eq!(
+            config.llvm_config_filepath(),
+            Path::new("/usr/bin/llvm-config")
+        );
+        assert_eq!(config.clang_filepath(), Path::new("/opt/llvm/bin/clang-17"));
+        assert_eq!(
+            config.source("clang_filepath"),
+            Some(&ConfigSource::Env("LLVM_CC_NAME".into()))
+        );
+        assert_eq!(
+            config.clangxx_filepath(),
+            Path::new("/opt/llvm/bin/clang++")
+        );
+        assert_eq!(
+            config.llvm_ar_filepath(),
+            Path::new("/opt/llvm/bin/llvm-ar")
+        );
+        assert_eq!(
+            config.source("llvm_ar_filepath"),
+            Some(&ConfigSource::Env("LLVM_COMPILER_PATH".into()))
+        );
+        assert!(config.is_configure_only());
+        assert_eq!(
+            config.bitcode_generation_flags(),
+            Some(&vec![
+                "-flto".to_string(),
+                "-fwhole-program-vtables".to_string()
+            ])
+        );
+        assert_eq!(
+            config.source("bitcode_store_path"),
+            Some(&ConfigSource::Env("RLLVM_BITCODE_STORE_PATH".into()))
+        );
+
+        // Only tools with given names are configured without `LLVM_COMPILER_PATH`
+        let layers = gllvm_env_config_layers(vars(&[("LLVM_LINK_NAME", "/usr/bin/llvm-link")]))
+            .expect("Failed to read environment variables");
+        assert_eq!(layers.len(), 1);
+        assert_eq!(
+            layers[0].1.get("llvm_link_filepath"),
+            Some(&toml::Value::String("/usr/bin/llvm-link".into()))
+        );
     }
 }
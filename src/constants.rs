@@ -8,15 +8,113 @@ type CallbackMap = HashMap<&'static str, ArgInfo<String>>;
 type PatternCallbackVec = Vec<ArgPatternInfo<String>>;
 
 pub const DARWIN_SEGMENT_NAME: &str = "__RLLVM";
+/// The segment name of bitcode sections written by gllvm and wllvm, which share
+/// the section names with rllvm
+pub const GLLVM_DARWIN_SEGMENT_NAME: &str = "__WLLVM";
 pub const DARWIN_SECTION_NAME: &str = "__llvm_bc";
 pub const ELF_SECTION_NAME: &str = ".llvm_bc";
 
+/// Magic bytes that start every record in the bitcode section
+pub const BITCODE_SECTION_MAGIC: &[u8; 8] = b"RLLVMBC\0";
+/// The current version of the bitcode section record format
+pub const BITCODE_SECTION_VERSION: u16 = 1;
+
+/// The current version of the JSON schema of parsed compiler arguments
+pub const COMPILER_ARGS_INFO_SCHEMA_VERSION: u32 = 1;
+
+/// Compile flags that make the object file lowered from the bitcode differ from
+/// the one compiled from the source, or whose side outputs are named after the
+/// output file, e.g., dependency files, coverage notes and split DWARF
+pub const SINGLE_COMPILE_INCOMPATIBLE_FLAG_PREFIXES: &[&str] = &[
+    "-M",
+    "-Xclang",
+    "-save-temps",
+    "-gsplit-dwarf",
+    "-fprofile-arcs",
+    "-ftest-coverage",
+    "-coverage",
+    "--coverage",
+    "-fembed-bitcode",
+    "-ftime-trace",
+    "-fsyntax-only",
+];
+
+/// Extensions of source files, and their languages as named by `-x`
+pub const SOURCE_FILE_LANGUAGES: &[(&str, &str)] = &[
+    ("c", "c"),
+    ("i", "cpp-output"),
+    ("h", "c-header"),
+    ("C", "c++"),
+    ("cc", "c++"),
+    ("CC", "c++"),
+    ("cp", "c++"),
+    ("cpp", "c++"),
+    ("CPP", "c++"),
+    ("cxx", "c++"),
+    ("CXX", "c++"),
+    ("c++", "c++"),
+    ("C++", "c++"),
+    ("ii", "c++-cpp-output"),
+    ("cppm", "c++-module"),
+    ("ccm", "c++-module"),
+    ("cxxm", "c++-module"),
+    ("c++m", "c++-module"),
+    ("ixx", "c++-module"),
+    ("H", "c++-header"),
+    ("hh", "c++-header"),
+    ("hpp", "c++-header"),
+    ("hxx", "c++-header"),
+    ("h++", "c++-header"),
+    ("m", "objective-c"),
+    ("mi", "objective-c-cpp-output"),
+    ("M", "objective-c++"),
+    ("mm", "objective-c++"),
+    ("mii", "objective-c++-cpp-output"),
+    ("s", "assembler"),
+    ("S", "assembler-with-cpp"),
+    ("sx", "assembler-with-cpp"),
+    ("cl", "cl"),
+    ("clcpp", "clcpp"),
+    ("cu", "cuda"),
+    ("hip", "hip"),
+    ("bc", "ir"),
+    ("ll", "ir"),
+];
+
+/// The maximum total length of arguments passed on the command line, beyond
+/// which arguments are passed by a response file instead
+pub const RESPONSE_FILE_ARGS_LENGTH_THRESHOLD: usize = 32 * 1024;
+/// The maximum nesting depth of response files
+pub const MAX_RESPONSE_FILE_DEPTH: usize = 32;
+/// The maximum number of attempts to create a unique temporary response file
+pub const MAX_RESPONSE_FILE_CREATION_ATTEMPTS: usize = 64;
+
+/// The range of exit codes for failures of rllvm itself, which is distinct from
+/// exit codes of the wrapped compiler
+pub const RLLVM_EXIT_CODE_MIN: i32 = 200;
+pub const RLLVM_EXIT_CODE_MAX: i32 = 209;
+
 /// Environment variables
 pub const DEFAULT_RLLVM_CONF_FILEPATH_ENV_NAME: &str = "RLLVM_CONFIG";
 pub const HOME_ENV_NAME: &str = "HOME";
+/// The prefix of environment variables that override configuration keys
+pub const CONF_ENV_NAME_PREFIX: &str = "RLLVM_";
+/// Environment variables of gllvm and wllvm
+pub const GLLVM_COMPILER_PATH_ENV_NAME: &str = "LLVM_COMPILER_PATH";
+pub const GLLVM_CC_NAME_ENV_NAME: &str = "LLVM_CC_NAME";
+pub const GLLVM_CXX_NAME_ENV_NAME: &str = "LLVM_CXX_NAME";
+pub const GLLVM_LINK_NAME_ENV_NAME: &str = "LLVM_LINK_NAME";
+pub const GLLVM_AR_NAME_ENV_NAME: &str = "LLVM_AR_NAME";
+pub const GLLVM_CONFIGURE_ONLY_ENV_NAME: &str = "WLLVM_CONFIGURE_ONLY";
+pub const GLLVM_BC_STORE_ENV_NAME: &str = "WLLVM_BC_STORE";
+pub const GLLVM_BITCODE_GENERATION_FLAGS_ENV_NAME: &str = "LLVM_BITCODE_GENERATION_FLAGS";
 
 /// The default filepath of the configuration file
 pub const DEFAULT_CONF_FILEPATH_UNDER_HOME: &str = ".rllvm/config.toml";
+/// The filepath of the system configuration file
+pub const SYSTEM_CONF_FILEPATH: &str = "/etc/rllvm/config.toml";
+/// The filename of project configuration files
+pub const PROJECT_CONF_FILENAME: &str = ".rllvm.toml";
 
 /// The max version of `LLVM` we're looking for
 #[cfg(not(target_vendor = "apple"))]
@@ -219,13 +317,13 @@ pub fn arg_exact_match_map() -> &'static CallbackMap {
         m.insert("-MM", ArgInfo::new(0, CompilerArgsInfo::dependency_only));
         m.insert("-MF", ArgInfo::new(1, CompilerArgsInfo::dependency_binary));
         m.insert("-MJ", ArgInfo::new(1, CompilerArgsInfo::dependency_binary));
-        m.insert("-MG", ArgInfo::new(0, CompilerArgsInfo::dependency_only));
-        m.insert("-MP", ArgInfo::new(0, CompilerArgsInfo::dependency_only));
+        m.insert("-MG", ArgInfo::new(0, CompilerArgsInfo::dependency_unary));
+        m.insert("-MP", ArgInfo::new(0, CompilerArgsInfo::dependency_unary));
         m.insert("-MT", ArgInfo::new(1, CompilerArgsInfo::dependency_binary));
         m.insert("-MQ", ArgInfo::new(1, CompilerArgsInfo::dependency_binary));
-        m.insert("-MD", ArgInfo::new(0, CompilerArgsInfo::dependency_only));
-        m.insert("-MV", ArgInfo::new(0, CompilerArgsInfo::dependency_only));
-        m.insert("-MMD", ArgInfo::new(0, CompilerArgsInfo::dependency_only));
+        m.insert("-MD", ArgInfo::new(0, CompilerArgsInfo::dependency_unary));
+        m.insert("-MV", ArgInfo::new(0, CompilerArgsInfo::dependency_unary));
+        m.insert("-MMD", ArgInfo::new(0, CompilerArgsInfo::dependency_unary));
 
         m.insert("-I", ArgInfo::new(1, CompilerArgsInfo::compile_binary));
         m.insert(
@@ -271,7 +369,7 @@ pub fn arg_exact_match_map() -> &'static CallbackMap {
             "-pedantic",
             ArgInfo::new(0, CompilerArgsInfo::compile_unary),
         );
-        m.insert("-x", ArgInfo::new(1, CompilerArgsInfo::compile_binary));
+        m.insert("-x", ArgInfo::new(1, CompilerArgsInfo::language));
 
         m.insert("-g", ArgInfo::new(0, CompilerArgsInfo::compile_unary));
         m.insert("-g0", ArgInfo::new(0, CompilerArgsInfo::compile_unary));
@@ -423,10 +521,13 @@ pub fn arg_patterns() -> &'static PatternCallbackVec {
     static ARG_PATTERNS: OnceLock<PatternCallbackVec> = OnceLock::new();
     ARG_PATTERNS.get_or_init(|| {
         vec![
-            ArgPatternInfo::new(r"^-MF.*$", 0, CompilerArgsInfo::compile_unary),
-            ArgPatternInfo::new(r"^-MJ.*$", 0, CompilerArgsInfo::compile_unary),
-            ArgPatternInfo::new(r"^-MQ.*$", 0, CompilerArgsInfo::compile_unary),
-            ArgPatternInfo::new(r"^-MT.*$", 0, CompilerArgsInfo::compile_unary),
+            ArgPatternInfo::new(r"^-MF.*$", 0, CompilerArgsInfo::dependency_unary),
+            ArgPatternInfo::new(r"^-MJ.*$", 0, CompilerArgsInfo::dependency_unary),
+            ArgPatternInfo::new(r"^-MQ.*$", 0, CompilerArgsInfo::dependency_unary),
+            ArgPatternInfo::new(r"^-MT.*$", 0, CompilerArgsInfo::dependency_unary),
+            ArgPatternInfo::new(r"^-Wp,-MM?$", 0, CompilerArgsInfo::dependency_only),
+            ArgPatternInfo::new(r"^-Wp,-M.*$", 0, CompilerArgsInfo::dependency_unary),
+            ArgPatternInfo::new(r"^-x.+$", 0, CompilerArgsInfo::language),
             ArgPatternInfo::new(r"^-Wl,.+$", 0, CompilerArgsInfo::link_unary),
             ArgPatternInfo::new(r"^-W[^l].*$", 0, CompilerArgsInfo::compile_unary),
             ArgPatternInfo::new(r"^-W[l][^,].*$", 0, CompilerArgsInfo::compile_unary),
@@ -471,7 +572,14 @@ pub fn arg_patterns() -> &'static PatternCallbackVec {
             ),
             ArgPatternInfo::new(r"^--param=.+$", 0, CompilerArgsInfo::compile_unary),
             ArgPatternInfo::new(
-                r"^.+\.(c|cc|cpp|C|cxx|i|s|S|bc)$",
+                &format!(
+                    r"^.+\.({})$",
+                    SOURCE_FILE_LANGUAGES
+                        .iter()
+                        .map(|(extension, _)| regex::escape(extension))
+                        .collect::<Vec<_>>()
+                        .join("|")
+                ),
                 0,
                 CompilerArgsInfo::input_file,
             ),
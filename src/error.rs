@@ -1,6 +1,8 @@
 //! rllvm error Type
 
-use std::{str::Utf8Error, string::FromUtf8Error};
+use std::{process::ExitStatus, str::Utf8Error, string::FromUtf8Error};
+
+use crate::constants::*;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -13,12 +15,29 @@ pub enum Error {
     /// Command execution failure
     #[error("Execution failure: {0}")]
     ExecutionFailure(String),
+    /// The wrapped command exited unsuccessfully
+    #[error("Command failure: command={command:?}, exit_status={status}")]
+    CommandFailure {
+        /// The command, including the program
+        command: Vec<String>,
+        /// The exit status of the command
+        status: ExitStatus,
+    },
     /// Object file read error
     #[error("Object read error: {0}")]
     ObjectReadError(#[from] object::read::Error),
     /// Object file write error
     #[error("Object write error: {0}")]
     ObjectWriteError(#[from] object::write::Error),
+    /// Malformed bitcode section
+    #[error("Malformed bitcode section: {0}")]
+    MalformedSection(String),
+    /// Stale or missing bitcode files
+    #[error("Stale bitcode: {0}")]
+    StaleBitcode(String),
+    /// Source files without bitcode
+    #[error("Incomplete bitcode: {0}")]
+    IncompleteBitcode(String),
     /// String error
     #[error("String error: {0}")]
     StringError(String),
@@ -28,11 +47,76 @@ pub enum Error {
     /// Missing file
     #[error("Missing file: {0}")]
     MissingFile(String),
+    /// Invalid configuration file
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+    /// The global configuration has been installed or loaded
+    #[error("The configuration has already been initialized")]
+    ConfigAlreadyInitialized,
+    /// Multiple errors occurred, e.g., in concurrent jobs
+    #[error("{} errors occurred:\n{}", .0.len(), .0.iter().map(|err| format!("- {err}")).collect::<Vec<_>>().join("\n"))]
+    Aggregated(Vec<Error>),
     /// Something else happened
     #[error("Unknown error: {0}")]
     Unknown(String),
 }
 
+impl Error {
+    /// Obtain the exit status of the first failed command, if the error is
+    /// caused by failed commands
+    pub fn command_status(&self) -> Option<ExitStatus> {
+        match self {
+            Self::CommandFailure { status, .. } => Some(*status),
+            Self::Aggregated(errors) => errors.iter().find_map(Self::command_status),
+            _ => None,
+        }
+    }
+
+    /// Obtain the exit code that reports the error
+    ///
+    /// Failed commands pass their exit codes through, where a command
+    /// terminated by a signal exits with `128 + signal`, like shells do. Other
+    /// errors exit with codes in [`RLLVM_EXIT_CODE_MIN`]..=[`RLLVM_EXIT_CODE_MAX`].
+    pub fn exit_code(&self) -> i32 {
+        if let Some(status) = self.command_status() {
+            return exit_code_of_status(status);
+        }
+
+        match self {
+            Self::Unknown(_) | Self::Aggregated(_) | Self::CommandFailure { .. } => {
+                RLLVM_EXIT_CODE_MIN
+            }
+            Self::InvalidArguments(_) => RLLVM_EXIT_CODE_MIN + 1,
+            Self::Io(_) => RLLVM_EXIT_CODE_MIN + 2,
+            Self::ExecutionFailure(_) => RLLVM_EXIT_CODE_MIN + 3,
+            Self::ObjectReadError(_) | Self::ObjectWriteError(_) => RLLVM_EXIT_CODE_MIN + 4,
+            Self::MalformedSection(_) => RLLVM_EXIT_CODE_MIN + 5,
+            Self::StaleBitcode(_) | Self::IncompleteBitcode(_) => RLLVM_EXIT_CODE_MIN + 6,
+            Self::MissingFile(_) => RLLVM_EXIT_CODE_MIN + 7,
+            Self::InvalidConfig(_) | Self::ConfigAlreadyInitialized => RLLVM_EXIT_CODE_MIN + 8,
+            Self::StringError(_) | Self::LoggerError(_) => RLLVM_EXIT_CODE_MAX,
+        }
+    }
+}
+
+/// Obtain the exit code of the exit status, where a process terminated by a
+/// signal has the exit code `128 + signal`
+pub fn exit_code_of_status(status: ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+
+    1
+}
+
 impl From<Utf8Error> for Error {
     fn from(value: Utf8Error) -> Self {
         Self::StringError(format!("{}", value))
@@ -44,3 +128,34 @@ impl From<FromUtf8Error> for Error {
         Self::StringError(format!("{}", value))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exit_code() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let command_failure = |raw| Error::CommandFailure {
+            command: vec!["clang".into()],
+            status: ExitStatus::from_raw(raw),
+        };
+
+        // Exit code 1
+        assert_eq!(command_failure(1 << 8).exit_code(), 1);
+        // Killed by SIGKILL
+        assert_eq!(command_failure(9).exit_code(), 128 + 9);
+        // The first failed command wins
+        let error = Error::Aggregated(vec![
+            Error::MissingFile("foo.c".into()),
+            command_failure(2 << 8),
+            command_failure(1 << 8),
+        ]);
+        assert_eq!(error.exit_code(), 2);
+
+        let error = Error::InvalidArguments("foo".into());
+        assert!((RLLVM_EXIT_CODE_MIN..=RLLVM_EXIT_CODE_MAX).contains(&error.exit_code()));
+    }
+}
@@ -6,7 +6,7 @@ use std::{
     process::{Command, ExitStatus, Output, Stdio},
 };
 
-use crate::error::Error;
+use crate::error::{Error, exit_code_of_status};
 
 pub fn execute_command_for_status<P, S>(
     program_filepath: P,
@@ -25,6 +25,47 @@ where
         .map_err(Error::Io)
 }
 
+/// Exit this process in the same way as the given exit status, i.e., with the
+/// same exit code, or by the same signal
+pub fn exit_with_status(status: ExitStatus) -> ! {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            // SAFETY: restoring the default action and raising the signal do
+            // not touch any memory of this process
+            unsafe {
+                libc::signal(signal, libc::SIG_DFL);
+                libc::raise(signal);
+            }
+        }
+    }
+
+    std::process::exit(exit_code_of_status(status))
+}
+
+/// Exit this process, reporting the error
+///
+/// A failed command has reported its diagnostics, so this process exits in the
+/// same way as the command, without adding any messages. If several errors
+/// occurred, the first failed command decides how this process exits, and only
+/// the other errors are reported.
+pub fn exit_with_error(name: &str, err: Error) -> ! {
+    if let Some(status) = err.command_status() {
+        if let Error::Aggregated(errors) = &err {
+            for err in errors {
+                if err.command_status().is_none() {
+                    eprintln!("{}: error: {}", name, err);
+                }
+            }
+        }
+        exit_with_status(status);
+    }
+
+    eprintln!("{}: error: {}", name, err);
+    std::process::exit(err.exit_code())
+}
+
 fn execute_command_for_output<P, S>(program_filepath: P, args: &[S]) -> Result<Output, Error>
 where
     P: AsRef<Path>,
@@ -4,17 +4,24 @@ use std::{
     collections::HashMap,
     fs,
     path::{Path, PathBuf},
-    str,
 };
 
 use object::{
     BinaryFormat, File, Object, ObjectComdat, ObjectKind, ObjectSection, ObjectSymbol,
     RelocationTarget, SectionFlags, SectionKind, SymbolFlags, SymbolSection, write,
 };
+use sha2::{Digest, Sha256};
 
 use crate::{
-    constants::{DARWIN_SECTION_NAME, DARWIN_SEGMENT_NAME, ELF_SECTION_NAME},
+    config::BitcodeEmbedding,
+    constants::{
+        DARWIN_SECTION_NAME, DARWIN_SEGMENT_NAME, ELF_SECTION_NAME, GLLVM_DARWIN_SEGMENT_NAME,
+    },
     error::Error,
+    utils::{
+        BitcodeDigest, BitcodePathResolver, BitcodeSectionRecord, PathPrefixMap,
+        apply_path_prefix_maps, decode_bitcode_section,
+    },
 };
 
 pub fn is_plain_file<P>(file: P) -> bool
@@ -41,6 +48,48 @@ where
     Ok(object_file.kind() == ObjectKind::Relocatable)
 }
 
+/// Embed the bitcode to the corresponding object file, in the given way
+///
+/// The given path prefix maps are applied to the embedded bitcode filepath,
+/// so that the object file does not depend on the build directory.
+pub fn embed_bitcode_to_object_file<P>(
+    bitcode_filepath: P,
+    object_filepath: P,
+    output_object_filepath: Option<P>,
+    bitcode_embedding: BitcodeEmbedding,
+    path_prefix_maps: &[PathPrefixMap],
+) -> Result<(), Error>
+where
+    P: AsRef<Path>,
+{
+    let bitcode_filepath = bitcode_filepath.as_ref();
+    let record = match bitcode_embedding {
+        BitcodeEmbedding::Filepath => bitcode_filepath_record(bitcode_filepath)?,
+        BitcodeEmbedding::Contents => bitcode_contents_record(bitcode_filepath, false)?,
+        BitcodeEmbedding::CompressedContents => bitcode_contents_record(bitcode_filepath, true)?,
+    };
+    let remapped_filepath = apply_path_prefix_maps(record.filepath(), path_prefix_maps);
+    let record = record.with_filepath(remapped_filepath);
+
+    embed_bitcode_record_to_object_file(&record, object_filepath, output_object_filepath)
+}
+
+/// Record in the object file that the bitcode of the source file is missing
+pub fn embed_missing_bitcode_to_object_file<P>(
+    src_filepath: P,
+    object_filepath: P,
+    reason: &str,
+    path_prefix_maps: &[PathPrefixMap],
+) -> Result<(), Error>
+where
+    P: AsRef<Path>,
+{
+    let src_filepath = apply_path_prefix_maps(src_filepath, path_prefix_maps);
+    let record = BitcodeSectionRecord::missing(src_filepath, reason);
+
+    embed_bitcode_record_to_object_file(&record, object_filepath, None)
+}
+
 /// Embed the path of the bitcode to the corresponding object file
 pub fn embed_bitcode_filepath_to_object_file<P>(
     bitcode_filepath: P,
@@ -50,7 +99,74 @@ pub fn embed_bitcode_filepath_to_object_file<P>(
 where
     P: AsRef<Path>,
 {
-    let bitcode_filepath = bitcode_filepath.as_ref();
+    let record = bitcode_filepath_record(bitcode_filepath.as_ref())?;
+
+    embed_bitcode_record_to_object_file(&record, object_filepath, output_object_filepath)
+}
+
+/// Embed the contents of the bitcode, along with its path, to the
+/// corresponding object file, so that the object file is self-contained
+pub fn embed_bitcode_contents_to_object_file<P>(
+    bitcode_filepath: P,
+    object_filepath: P,
+    output_object_filepath: Option<P>,
+    is_compressed: bool,
+) -> Result<(), Error>
+where
+    P: AsRef<Path>,
+{
+    let record = bitcode_contents_record(bitcode_filepath.as_ref(), is_compressed)?;
+
+    embed_bitcode_record_to_object_file(&record, object_filepath, output_object_filepath)
+}
+
+fn absolute_bitcode_filepath(bitcode_filepath: &Path) -> Result<PathBuf, Error> {
+    if bitcode_filepath.is_absolute() {
+        Ok(bitcode_filepath.to_path_buf())
+    } else {
+        Ok(bitcode_filepath.canonicalize()?)
+    }
+}
+
+/// Create the record referencing the bitcode file by its path and digest
+fn bitcode_filepath_record(bitcode_filepath: &Path) -> Result<BitcodeSectionRecord, Error> {
+    let bitcode_filepath = absolute_bitcode_filepath(bitcode_filepath)?;
+
+    let mut record = BitcodeSectionRecord::new(&bitcode_filepath);
+    if is_plain_file(&bitcode_filepath) {
+        record = record.with_digest(calculate_file_digest(&bitcode_filepath)?);
+    } else {
+        log::warn!(
+            "Embed the bitcode filepath without digest, as the file does not exist: {:?}",
+            bitcode_filepath
+        );
+    }
+
+    Ok(record)
+}
+
+/// Create the record carrying the contents of the bitcode file
+fn bitcode_contents_record(
+    bitcode_filepath: &Path,
+    is_compressed: bool,
+) -> Result<BitcodeSectionRecord, Error> {
+    let bitcode_filepath = absolute_bitcode_filepath(bitcode_filepath)?;
+
+    let contents = fs::read(&bitcode_filepath)?;
+    BitcodeSectionRecord::new(&bitcode_filepath)
+        .with_digest(Sha256::digest(&contents).into())
+        .with_contents(&contents, is_compressed)
+}
+
+/// Append the record to the bitcode section of the object file
+pub fn embed_bitcode_record_to_object_file<P>(
+    record: &BitcodeSectionRecord,
+    object_filepath: P,
+    output_object_filepath: Option<P>,
+) -> Result<(), Error>
+where
+    P: AsRef<Path>,
+{
     let object_filepath = object_filepath.as_ref();
 
     let data = fs::read(object_filepath)?;
@@ -69,7 +185,12 @@ where
             DARWIN_SECTION_NAME.as_bytes().to_vec(),
             SectionFlags::MachO { flags: 0 },
         ),
-        _ => unimplemented!(),
+        _ => {
+            return Err(Error::InvalidArguments(format!(
+                "Unsupported binary format: {:?}",
+                object_binary_format
+            )));
+        }
     };
 
     // Copy the input object file into a new mutable object file
@@ -79,12 +200,7 @@ where
     let section_id = new_object_file.add_section(segment_name, section_name, SectionKind::Unknown);
     let new_section = new_object_file.section_mut(section_id);
 
-    let bitcode_filepath_string = if bitcode_filepath.is_absolute() {
-        bitcode_filepath.to_string_lossy().to_string()
-    } else {
-        format!("{}\n", bitcode_filepath.canonicalize()?.to_string_lossy())
-    };
-    new_section.set_data(bitcode_filepath_string.as_bytes(), 1);
+    new_section.set_data(record.encode()?, 1);
     // NOTE: we have to explicitly set flags; otherwise, the flags will be
     // inferred based on the section kind, but `Section::Unknown` is not
     // supported for auto inferring flags
@@ -265,38 +381,191 @@ fn copy_object_file(in_object: File) -> Result<write::Object, Error> {
     Ok(out_object)
 }
 
-/// Extract the path of the bitcode from the parsed object
-pub fn extract_bitcode_filepaths_from_parsed_object(
-    object_file: &object::File,
-) -> Result<Vec<PathBuf>, Error> {
-    let object_binary_format = object_file.format();
+/// Calculate the SHA-256 digest of the given file
+pub fn calculate_file_digest<P>(filepath: P) -> Result<BitcodeDigest, Error>
+where
+    P: AsRef<Path>,
+{
+    let data = fs::read(filepath)?;
+    Ok(Sha256::digest(data).into())
+}
+
+/// Format the digest as a lowercase hexadecimal string
+pub fn format_digest(digest: &BitcodeDigest) -> String {
+    digest.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Status of a bitcode file referenced by the bitcode section
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BitcodeFileStatus {
+    /// The file matches the embedded digest
+    Intact,
+    /// The file exists, but no digest was embedded to check it against
+    Unverified,
+    /// The file does not exist
+    Missing,
+    /// The file has been overwritten since it was embedded
+    Stale {
+        expected: BitcodeDigest,
+        actual: BitcodeDigest,
+    },
+}
+
+impl BitcodeFileStatus {
+    /// Returns `true` if the file is missing or stale
+    pub fn is_invalid(&self) -> bool {
+        matches!(self, Self::Missing | Self::Stale { .. })
+    }
+}
+
+/// Check the bitcode referenced by the record against its digest
+///
+/// For self-contained records, the embedded contents are checked instead of
+/// the bitcode file.
+pub fn check_bitcode_record(record: &BitcodeSectionRecord) -> Result<BitcodeFileStatus, Error> {
+    let bitcode_filepath = record.filepath();
+    let contents = record.contents()?;
+    if contents.is_none() && !is_plain_file(bitcode_filepath) {
+        return Ok(BitcodeFileStatus::Missing);
+    }
+
+    let Some(&expected) = record.digest() else {
+        return Ok(BitcodeFileStatus::Unverified);
+    };
+    let actual = match contents {
+        Some(contents) => Sha256::digest(contents).into(),
+        None => calculate_file_digest(bitcode_filepath)?,
+    };
+    if actual == expected {
+        Ok(BitcodeFileStatus::Intact)
+    } else {
+        Ok(BitcodeFileStatus::Stale { expected, actual })
+    }
+}
 
-    let section_name = match object_binary_format {
-        BinaryFormat::Elf => ELF_SECTION_NAME.as_bytes(),
-        BinaryFormat::MachO => DARWIN_SECTION_NAME.as_bytes(),
-        _ => unimplemented!("unsupported binary format: {:?}", object_binary_format),
+/// Write the embedded contents of the record into the given directory, and
+/// return the filepath of the materialized bitcode file
+///
+/// Records without contents are returned as is, i.e., their filepaths.
+pub fn materialize_bitcode_record<P>(
+    record: &BitcodeSectionRecord,
+    output_dir: P,
+) -> Result<PathBuf, Error>
+where
+    P: AsRef<Path>,
+{
+    let Some(contents) = record.contents()? else {
+        return Ok(record.filepath().clone());
     };
 
-    match object_file.section_by_name_bytes(section_name) {
-        Some(section) => {
-            let section_data = section.data()?;
-            let embedded_filepath_string = str::from_utf8(section_data)?.trim();
+    // Name the materialized module after the original bitcode file, and
+    // disambiguate by the digest of its contents
+    let digest = format_digest(&Sha256::digest(&contents).into());
+    let file_stem = record
+        .filepath()
+        .file_stem()
+        .map_or("module".into(), |x| x.to_string_lossy());
+    let bitcode_filepath = output_dir
+        .as_ref()
+        .join(format!("{}_{}.bc", file_stem, &digest[..16]));
 
-            let mut embedded_filepaths: Vec<_> = embedded_filepath_string
-                .split('\n')
-                .map(PathBuf::from)
-                .collect();
+    fs::write(&bitcode_filepath, contents)?;
 
-            // Sort
-            embedded_filepaths.sort();
+    Ok(bitcode_filepath)
+}
 
-            // Deduplicate
-            embedded_filepaths.dedup();
+/// Check if the section is a bitcode section, written by either rllvm, gllvm
+/// or wllvm
+fn is_bitcode_section(
+    section: &object::Section,
+    binary_format: BinaryFormat,
+) -> Result<bool, Error> {
+    let is_bitcode_section = match binary_format {
+        BinaryFormat::Elf => section.name_bytes()? == ELF_SECTION_NAME.as_bytes(),
+        // A linked Mach-O file may contain both `__RLLVM,__llvm_bc` and
+        // `__WLLVM,__llvm_bc`
+        BinaryFormat::MachO => {
+            section.name_bytes()? == DARWIN_SECTION_NAME.as_bytes()
+                && section.segment_name_bytes()?.is_some_and(|segment_name| {
+                    [DARWIN_SEGMENT_NAME, GLLVM_DARWIN_SEGMENT_NAME]
+                        .iter()
+                        .any(|name| segment_name == name.as_bytes())
+                })
+        }
+        _ => {
+            return Err(Error::InvalidArguments(format!(
+                "Unsupported binary format: {:?}",
+                binary_format
+            )));
+        }
+    };
+
+    Ok(is_bitcode_section)
+}
+
+/// Extract the bitcode section records from the parsed object
+///
+/// Sections written by gllvm and wllvm contain newline-separated bitcode
+/// filepaths, which are decoded as the legacy format.
+pub fn extract_bitcode_records_from_parsed_object(
+    object_file: &object::File,
+) -> Result<Vec<BitcodeSectionRecord>, Error> {
+    let object_binary_format = object_file.format();
 
-            Ok(embedded_filepaths)
+    let mut records = vec![];
+    for section in object_file.sections() {
+        if is_bitcode_section(&section, object_binary_format)? {
+            records.extend(decode_bitcode_section(section.data()?)?);
         }
-        None => Ok(vec![]),
     }
+
+    // Sort
+    records.sort();
+
+    // Deduplicate
+    records.dedup();
+
+    Ok(records)
+}
+
+/// Obtain the bitcode filepaths of the records
+///
+/// Records of source files without bitcode are reported as
+/// [`Error::IncompleteBitcode`], as their filepaths are not bitcode files.
+fn bitcode_filepaths_of_records(records: Vec<BitcodeSectionRecord>) -> Result<Vec<PathBuf>, Error> {
+    let missing_filepaths: Vec<_> = records
+        .iter()
+        .filter(|record| record.missing_reason().is_some())
+        .map(|record| record.filepath())
+        .collect();
+    if !missing_filepaths.is_empty() {
+        return Err(Error::IncompleteBitcode(format!(
+            "Found {} source file(s) without bitcode: {:?}",
+            missing_filepaths.len(),
+            missing_filepaths
+        )));
+    }
+
+    Ok(records
+        .into_iter()
+        .map(|record| record.filepath().clone())
+        .collect())
+}
+
+/// Extract the path of the bitcode from the parsed object
+///
+/// Source files without bitcode are reported as [`Error::IncompleteBitcode`];
+/// use [`extract_bitcode_records_from_parsed_object`] to inspect them.
+pub fn extract_bitcode_filepaths_from_parsed_object(
+    object_file: &object::File,
+) -> Result<Vec<PathBuf>, Error> {
+    let mut embedded_filepaths =
+        bitcode_filepaths_of_records(extract_bitcode_records_from_parsed_object(object_file)?)?;
+
+    // Deduplicate
+    embedded_filepaths.dedup();
+
+    Ok(embedded_filepaths)
 }
 
 /// Extract the path of the bitcode from the corresponding object file
@@ -314,6 +583,26 @@ where
     extract_bitcode_filepaths_from_parsed_object(&object_file)
 }
 
+pub fn extract_bitcode_records_from_parsed_objects(
+    object_files: &[object::File],
+) -> Result<Vec<BitcodeSectionRecord>, Error> {
+    let mut records = vec![];
+    for object_file in object_files {
+        records.extend(extract_bitcode_records_from_parsed_object(object_file)?);
+    }
+
+    // Sort
+    records.sort();
+
+    // Deduplicate
+    records.dedup();
+
+    Ok(records)
+}
+
+/// Extract the path of the bitcode from the parsed objects
+///
+/// Source files without bitcode are reported as [`Error::IncompleteBitcode`].
 pub fn extract_bitcode_filepaths_from_parsed_objects(
     object_files: &[object::File],
 ) -> Result<Vec<PathBuf>, Error> {
@@ -331,6 +620,47 @@ pub fn extract_bitcode_filepaths_from_parsed_objects(
     Ok(bitcode_filepaths)
 }
 
+/// Extract the bitcode section records from the parsed objects, resolving
+/// the embedded filepaths with the given resolver
+pub fn resolve_bitcode_records_from_parsed_objects(
+    object_files: &[object::File],
+    resolver: &BitcodePathResolver,
+) -> Result<Vec<BitcodeSectionRecord>, Error> {
+    let mut records: Vec<_> = extract_bitcode_records_from_parsed_objects(object_files)?
+        .into_iter()
+        .map(|record| {
+            let filepath = resolver.resolve(record.filepath());
+            record.with_filepath(filepath)
+        })
+        .collect();
+
+    // Sort
+    records.sort();
+
+    // Deduplicate
+    records.dedup();
+
+    Ok(records)
+}
+
+/// Extract the path of the bitcode from the parsed objects, resolving the
+/// embedded filepaths with the given resolver
+///
+/// Source files without bitcode are reported as [`Error::IncompleteBitcode`].
+pub fn resolve_bitcode_filepaths_from_parsed_objects(
+    object_files: &[object::File],
+    resolver: &BitcodePathResolver,
+) -> Result<Vec<PathBuf>, Error> {
+    let mut bitcode_filepaths = bitcode_filepaths_of_records(
+        resolve_bitcode_records_from_parsed_objects(object_files, resolver)?,
+    )?;
+
+    // Deduplicate
+    bitcode_filepaths.dedup();
+
+    Ok(bitcode_filepaths)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -369,6 +699,268 @@ mod tests {
         fs::remove_file(output_object_filepath).expect("Failed to delete the output object file");
     }
 
+    #[test]
+    fn test_bitcode_staleness_detection() {
+        let bitcode_filepath = Path::new("/tmp/staleness.bc");
+        let object_filepath = Path::new(test_case!("hello.o"));
+        let output_object_filepath = Path::new("/tmp/staleness.new.o");
+
+        fs::write(bitcode_filepath, b"original").expect("Failed to write the bitcode file");
+        embed_bitcode_filepath_to_object_file(
+            bitcode_filepath,
+            object_filepath,
+            Some(output_object_filepath),
+        )
+        .expect("Failed to embed the bitcode filepath");
+
+        let data = fs::read(output_object_filepath).expect("Failed to read the object file");
+        let object_file = object::File::parse(&*data).expect("Failed to parse the object file");
+        let records = extract_bitcode_records_from_parsed_object(&object_file)
+            .expect("Failed to extract records");
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(
+            record.digest(),
+            Some(&calculate_file_digest(bitcode_filepath).unwrap())
+        );
+
+        // Intact
+        assert_eq!(
+            check_bitcode_record(record).unwrap(),
+            BitcodeFileStatus::Intact
+        );
+
+        // Overwritten by a different compile
+        fs::write(bitcode_filepath, b"overwritten").expect("Failed to write the bitcode file");
+        assert!(matches!(
+            check_bitcode_record(record).unwrap(),
+            BitcodeFileStatus::Stale { .. }
+        ));
+
+        // Removed
+        fs::remove_file(bitcode_filepath).expect("Failed to delete the bitcode file");
+        assert_eq!(
+            check_bitcode_record(record).unwrap(),
+            BitcodeFileStatus::Missing
+        );
+
+        // Clean
+        fs::remove_file(output_object_filepath).expect("Failed to delete the output object file");
+    }
+
+    #[test]
+    fn test_contents_embedding_and_materialization() {
+        let bitcode_filepath = Path::new("/tmp/self_contained.bc");
+        let object_filepath = Path::new(test_case!("hello.o"));
+        let output_object_filepath = Path::new("/tmp/self_contained.new.o");
+        let output_dir = Path::new("/tmp/self_contained_modules");
+        fs::create_dir_all(output_dir).expect("Failed to create the output directory");
+
+        let contents = b"BC\xc0\xde self-contained bitcode".to_vec();
+        fs::write(bitcode_filepath, &contents).expect("Failed to write the bitcode file");
+        embed_bitcode_to_object_file(
+            bitcode_filepath,
+            object_filepath,
+            Some(output_object_filepath),
+            BitcodeEmbedding::CompressedContents,
+            &[],
+        )
+        .expect("Failed to embed the bitcode contents");
+
+        // The bitcode file is no longer needed
+        fs::remove_file(bitcode_filepath).expect("Failed to delete the bitcode file");
+
+        let data = fs::read(output_object_filepath).expect("Failed to read the object file");
+        let object_file = object::File::parse(&*data).expect("Failed to parse the object file");
+        let records = extract_bitcode_records_from_parsed_object(&object_file)
+            .expect("Failed to extract records");
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            check_bitcode_record(&records[0]).unwrap(),
+            BitcodeFileStatus::Intact
+        );
+
+        let materialized_filepath = materialize_bitcode_record(&records[0], output_dir)
+            .expect("Failed to materialize the record");
+        assert!(materialized_filepath.starts_with(output_dir));
+        assert_eq!(fs::read(&materialized_filepath).unwrap(), contents);
+
+        // Clean
+        fs::remove_dir_all(output_dir).expect("Failed to delete the output directory");
+        fs::remove_file(output_object_filepath).expect("Failed to delete the output object file");
+    }
+
+    #[test]
+    fn test_path_prefix_map_embedding() {
+        let bitcode_filepath = Path::new("/tmp/prefix_map/hello.bc");
+        let object_filepath = Path::new(test_case!("hello.o"));
+        let output_object_filepath = Path::new("/tmp/prefix_map.new.o");
+
+        embed_bitcode_to_object_file(
+            bitcode_filepath,
+            object_filepath,
+            Some(output_object_filepath),
+            BitcodeEmbedding::Filepath,
+            &["/tmp/prefix_map=/build".parse().unwrap()],
+        )
+        .expect("Failed to embed the bitcode filepath");
+
+        let embedded_filepaths = extract_bitcode_filepaths_from_object_file(output_object_filepath)
+            .expect("Failed to extract embedded filepaths");
+        assert_eq!(embedded_filepaths, vec![PathBuf::from("/build/hello.bc")]);
+
+        // Clean
+        fs::remove_file(output_object_filepath).expect("Failed to delete the output object file");
+    }
+
+    #[test]
+    fn test_gllvm_sections_extraction() {
+        let rllvm_record = BitcodeSectionRecord::new("/tmp/rllvm.bc").encode().unwrap();
+
+        // gllvm and rllvm share the ELF section
+        let mut new_object_file = write::Object::new(
+            BinaryFormat::Elf,
+            object::Architecture::X86_64,
+            object::Endianness::Little,
+        );
+        let section_id = new_object_file.add_section(
+            vec![],
+            ELF_SECTION_NAME.as_bytes().to_vec(),
+            SectionKind::Unknown,
+        );
+        let section = new_object_file.section_mut(section_id);
+        section.set_data(
+            [b"/tmp/gllvm.bc\n/tmp/wllvm.bc\n".as_slice(), &rllvm_record].concat(),
+            1,
+        );
+        section.flags = SectionFlags::Elf { sh_flags: 0 };
+        let data = new_object_file
+            .write()
+            .expect("Failed to write the object file");
+        let object_file = object::File::parse(&*data).expect("Failed to parse the object file");
+        assert_eq!(
+            extract_bitcode_filepaths_from_parsed_object(&object_file).unwrap(),
+            vec![
+                PathBuf::from("/tmp/gllvm.bc"),
+                PathBuf::from("/tmp/rllvm.bc"),
+                PathBuf::from("/tmp/wllvm.bc"),
+            ]
+        );
+
+        // gllvm and rllvm use different Mach-O segments
+        let mut new_object_file = write::Object::new(
+            BinaryFormat::MachO,
+            object::Architecture::Aarch64,
+            object::Endianness::Little,
+        );
+        for (segment_name, section_data) in [
+            (DARWIN_SEGMENT_NAME, rllvm_record.as_slice()),
+            (GLLVM_DARWIN_SEGMENT_NAME, b"/tmp/gllvm.bc\n".as_slice()),
+            ("__DATA", b"/tmp/unrelated.bc\n".as_slice()),
+        ] {
+            let section_id = new_object_file.add_section(
+                segment_name.as_bytes().to_vec(),
+                DARWIN_SECTION_NAME.as_bytes().to_vec(),
+                SectionKind::Unknown,
+            );
+            let section = new_object_file.section_mut(section_id);
+            section.set_data(section_data, 1);
+            section.flags = SectionFlags::MachO { flags: 0 };
+        }
+        let data = new_object_file
+            .write()
+            .expect("Failed to write the object file");
+        let object_file = object::File::parse(&*data).expect("Failed to parse the object file");
+        assert_eq!(
+            extract_bitcode_filepaths_from_parsed_object(&object_file).unwrap(),
+            vec![
+                PathBuf::from("/tmp/gllvm.bc"),
+                PathBuf::from("/tmp/rllvm.bc")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unsupported_binary_format() {
+        let mut new_object_file = write::Object::new(
+            BinaryFormat::Coff,
+            object::Architecture::X86_64,
+            object::Endianness::Little,
+        );
+        new_object_file.add_section(vec![], b".text".to_vec(), SectionKind::Text);
+        let data = new_object_file
+            .write()
+            .expect("Failed to write the object file");
+        let object_file = object::File::parse(&*data).expect("Failed to parse the object file");
+        assert!(matches!(
+            extract_bitcode_records_from_parsed_object(&object_file),
+            Err(Error::InvalidArguments(_))
+        ));
+
+        let object_filepath = Path::new("/tmp/rllvm_test_unsupported_binary_format.obj");
+        fs::write(object_filepath, &data).expect("Failed to write the object file");
+        assert!(matches!(
+            embed_bitcode_record_to_object_file(
+                &BitcodeSectionRecord::new("/tmp/foo.bc"),
+                object_filepath,
+                None
+            ),
+            Err(Error::InvalidArguments(_))
+        ));
+
+        // Clean
+        fs::remove_file(object_filepath).expect("Failed to delete the object file");
+    }
+
+    #[test]
+    fn test_missing_bitcode_extraction() {
+        let mut new_object_file = write::Object::new(
+            BinaryFormat::Elf,
+            object::Architecture::X86_64,
+            object::Endianness::Little,
+        );
+        let section_id = new_object_file.add_section(
+            vec![],
+            ELF_SECTION_NAME.as_bytes().to_vec(),
+            SectionKind::Unknown,
+        );
+        let section = new_object_file.section_mut(section_id);
+        section.set_data(
+            [
+                BitcodeSectionRecord::new("/tmp/foo.bc").encode().unwrap(),
+                BitcodeSectionRecord::missing("/tmp/bar.c", "exit status: 1")
+                    .encode()
+                    .unwrap(),
+            ]
+            .concat(),
+            1,
+        );
+        section.flags = SectionFlags::Elf { sh_flags: 0 };
+        let data = new_object_file
+            .write()
+            .expect("Failed to write the object file");
+        let object_file = object::File::parse(&*data).expect("Failed to parse the object file");
+
+        // The record is kept for inspection
+        let records = extract_bitcode_records_from_parsed_object(&object_file).unwrap();
+        assert_eq!(records.len(), 2);
+
+        // The source file is never returned as a bitcode filepath
+        let object_files = [object_file];
+        for result in [
+            extract_bitcode_filepaths_from_parsed_object(&object_files[0]),
+            extract_bitcode_filepaths_from_parsed_objects(&object_files),
+            resolve_bitcode_filepaths_from_parsed_objects(
+                &object_files,
+                &BitcodePathResolver::default(),
+            ),
+        ] {
+            assert!(
+                matches!(result, Err(Error::IncompleteBitcode(message)) if message.contains("/tmp/bar.c"))
+            );
+        }
+    }
+
     #[test]
     fn test_paths_extraction() {
         let object_filepath = Path::new(test_case!("foo_bar_baz.dylib"));
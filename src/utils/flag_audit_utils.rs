@@ -0,0 +1,81 @@
+//! Unknown-flag audit utility functions
+
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+use crate::error::Error;
+
+/// Append the unknown flags to the log, one per line
+///
+/// All flags are written at once in append mode, so that concurrent compiler
+/// invocations of one build do not interleave their lines.
+pub fn append_unknown_flags<P, S>(log_filepath: P, flags: &[S]) -> Result<(), Error>
+where
+    P: AsRef<Path>,
+    S: AsRef<str>,
+{
+    let mut contents = String::new();
+    for flag in flags {
+        contents.push_str(flag.as_ref());
+        contents.push('\n');
+    }
+
+    let mut log_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_filepath)?;
+    log_file.write_all(contents.as_bytes())?;
+
+    Ok(())
+}
+
+/// Aggregate the unknown flags in the log, i.e., count the occurrences of each
+/// flag, in the descending order of occurrences
+pub fn aggregate_unknown_flags<P>(log_filepath: P) -> Result<Vec<(String, usize)>, Error>
+where
+    P: AsRef<Path>,
+{
+    let contents = fs::read_to_string(log_filepath)?;
+
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for flag in contents.lines().filter(|line| !line.is_empty()) {
+        *counts.entry(flag).or_default() += 1;
+    }
+
+    let mut counts: Vec<_> = counts
+        .into_iter()
+        .map(|(flag, count)| (flag.to_string(), count))
+        .collect();
+    counts.sort_by(|(flag_a, count_a), (flag_b, count_b)| {
+        count_b.cmp(count_a).then_with(|| flag_a.cmp(flag_b))
+    });
+
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_flags_log() {
+        let log_filepath = Path::new("/tmp/rllvm_test_unknown_flags.log");
+        let _ = fs::remove_file(log_filepath);
+
+        append_unknown_flags(log_filepath, &["-mfoo", "-fbar"]).expect("Failed to append flags");
+        append_unknown_flags(log_filepath, &["-mfoo"]).expect("Failed to append flags");
+        append_unknown_flags::<_, &str>(log_filepath, &[]).expect("Failed to append flags");
+
+        assert_eq!(
+            aggregate_unknown_flags(log_filepath).unwrap(),
+            vec![("-mfoo".to_string(), 2), ("-fbar".to_string(), 1)]
+        );
+
+        // Clean
+        fs::remove_file(log_filepath).expect("Failed to delete the log");
+    }
+}
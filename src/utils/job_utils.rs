@@ -0,0 +1,105 @@
+//! Concurrent job execution utility functions
+
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, OnceLock},
+    thread,
+};
+
+use jobserver::Client;
+
+/// Obtain the GNU make jobserver advertised in the environment, if any
+pub fn jobserver_client() -> Option<&'static Client> {
+    static JOBSERVER_CLIENT: OnceLock<Option<Client>> = OnceLock::new();
+    JOBSERVER_CLIENT
+        .get_or_init(|| {
+            // SAFETY: the file descriptors advertised in `MAKEFLAGS` are
+            // inherited from make, and are not used elsewhere in this process
+            let client = unsafe { Client::from_env() };
+            if client.is_some() {
+                log::debug!("Use the jobserver advertised in the environment");
+            }
+            client
+        })
+        .as_ref()
+}
+
+/// Run the job on every input concurrently, and return the results in the
+/// order of the inputs
+///
+/// If the GNU make jobserver is available, one job runs on the implicit token
+/// of this process and every other job acquires a token from the jobserver.
+/// Otherwise, at most `max_jobs` jobs run at the same time.
+pub fn run_jobs<T, R, F>(inputs: Vec<T>, max_jobs: usize, job: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+{
+    let jobserver = jobserver_client();
+    let num_workers = if jobserver.is_some() {
+        inputs.len()
+    } else {
+        max_jobs.clamp(1, inputs.len().max(1))
+    };
+    if num_workers <= 1 {
+        return inputs.into_iter().map(job).collect();
+    }
+
+    let num_inputs = inputs.len();
+    let queue = Mutex::new(inputs.into_iter().enumerate().collect::<VecDeque<_>>());
+    let results = Mutex::new((0..num_inputs).map(|_| None).collect::<Vec<_>>());
+    thread::scope(|scope| {
+        for worker_index in 0..num_workers {
+            let (queue, results, job) = (&queue, &results, &job);
+            scope.spawn(move || {
+                loop {
+                    let Some((index, input)) = queue.lock().unwrap().pop_front() else {
+                        break;
+                    };
+
+                    // The first worker runs on the implicit token
+                    let token = match jobserver {
+                        Some(jobserver) if worker_index > 0 => jobserver
+                            .acquire()
+                            .inspect_err(|err| {
+                                log::warn!("Failed to acquire a jobserver token: err={}", err)
+                            })
+                            .ok(),
+                        _ => None,
+                    };
+                    let result = job(input);
+                    drop(token);
+
+                    results.lock().unwrap()[index] = Some(result);
+                }
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("Every job has a result"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_jobs_preserves_order() {
+        let inputs: Vec<usize> = (0..32).collect();
+        let results = run_jobs(inputs.clone(), 4, |x| x * 2);
+        assert_eq!(results, inputs.iter().map(|x| x * 2).collect::<Vec<_>>());
+
+        // Sequential
+        let results = run_jobs(inputs.clone(), 1, |x| x + 1);
+        assert_eq!(results, inputs.iter().map(|x| x + 1).collect::<Vec<_>>());
+
+        // No inputs
+        assert!(run_jobs(Vec::<usize>::new(), 4, |x| x).is_empty());
+    }
+}
@@ -11,8 +11,8 @@ use which::which;
 
 #[cfg(not(target_vendor = "apple"))]
 use crate::constants::{LLVM_VERSION_MAX, LLVM_VERSION_MIN};
-use crate::utils::{execute_command_for_status, execute_command_for_stdout_string};
-use crate::{config::rllvm_config, error::Error};
+use crate::utils::{ResponseFile, execute_command_for_status, execute_command_for_stdout_string};
+use crate::{config::RLLVMConfig, error::Error};
 
 pub fn execute_llvm_ar<P, S>(llvm_ar_filepath: P, args: &[S]) -> Result<ExitStatus, Error>
 where
@@ -101,11 +101,25 @@ pub fn find_llvm_config() -> Result<PathBuf, Error> {
     }
 }
 
+/// Execute the LLVM tool, passing long arguments by a response file, which is
+/// deleted after the execution
+fn execute_llvm_tool_for_status<P>(tool_filepath: P, args: &[String]) -> Result<Option<i32>, Error>
+where
+    P: AsRef<Path>,
+{
+    let status = match ResponseFile::try_new_if_too_long(args)? {
+        Some(response_file) => execute_command_for_status(tool_filepath, &[response_file.arg()])?,
+        None => execute_command_for_status(tool_filepath, args)?,
+    };
+
+    Ok(status.code())
+}
+
 /// Link given bitcode files into one bitcode file
 ///
-/// TODO: do we need to link bitcode files incrementally in case the command
-/// execeeds the limitation of `getconf ARG_MAX`?
+/// Too many bitcode files for a command line are passed by a response file.
 pub fn link_bitcode_files<P>(
+    config: &RLLVMConfig,
     bitcode_filepaths: &[P],
     output_filepath: P,
 ) -> Result<Option<i32>, Error>
@@ -116,7 +130,7 @@ where
 
     let mut args = vec![];
     // Link arguments
-    if let Some(llvm_link_flags) = rllvm_config().llvm_link_flags() {
+    if let Some(llvm_link_flags) = config.llvm_link_flags() {
         args.extend(llvm_link_flags.iter().cloned());
     }
     // Output
@@ -131,16 +145,16 @@ where
             .map(|x| String::from(x.as_ref().to_string_lossy())),
     );
 
-    execute_command_for_status(rllvm_config().llvm_link_filepath(), &args)
-        .map(|status| status.code())
+    execute_llvm_tool_for_status(config.llvm_link_filepath(), &args)
 }
 
 /// Archive given bitcode files into one archive file
 ///
-/// TODO:
-/// 1. do we need to archive files incrementally?
-/// 2. do we need to avoid absolute paths in the generated archive?
+/// Too many bitcode files for a command line are passed by a response file.
+///
+/// TODO: do we need to avoid absolute paths in the generated archive?
 pub fn archive_bitcode_files<P>(
+    config: &RLLVMConfig,
     bitcode_filepaths: &[P],
     output_filepath: P,
 ) -> Result<Option<i32>, Error>
@@ -160,7 +174,7 @@ where
             .map(|x| String::from(x.as_ref().to_string_lossy())),
     );
 
-    execute_command_for_status(rllvm_config().llvm_ar_filepath(), &args).map(|status| status.code())
+    execute_llvm_tool_for_status(config.llvm_ar_filepath(), &args)
 }
 
 #[cfg(test)]
@@ -168,6 +182,7 @@ mod tests {
     use super::*;
     use crate::{
         compiler_wrapper::{CompilerKind, CompilerWrapper, llvm::ClangWrapper},
+        config::rllvm_config,
         utils::test_case,
     };
     use std::{
@@ -240,7 +255,7 @@ mod tests {
         let output_filepath = Path::new("/tmp/foo_bar_baz.bc");
 
         assert!(
-            link_bitcode_files(&bitcode_filepaths, output_filepath).map_or_else(
+            link_bitcode_files(rllvm_config(), &bitcode_filepaths, output_filepath).map_or_else(
                 |err| {
                     println!("Failed to link bitcode files: {:?}", err);
                     false
@@ -273,7 +288,7 @@ mod tests {
         let output_filepath = Path::new("/tmp/foo_bar_baz.bca");
 
         assert!(
-            archive_bitcode_files(&bitcode_filepaths, output_filepath).map_or_else(
+            archive_bitcode_files(rllvm_config(), &bitcode_filepaths, output_filepath).map_or_else(
                 |err| {
                     println!("Failed to archive bitcode files: {:?}", err);
                     false
@@ -8,10 +8,26 @@ pub use command_utils::*;
 mod file_utils;
 pub use file_utils::*;
 
+/// Unknown-flag audit utility functions
+mod flag_audit_utils;
+pub use flag_audit_utils::*;
+
+/// Concurrent job execution utility functions
+mod job_utils;
+pub use job_utils::*;
+
 /// LLVM-related utility functions
 mod llvm_utils;
 pub use llvm_utils::*;
 
+/// Response file (`@file`) utility functions
+mod response_file_utils;
+pub use response_file_utils::*;
+
+/// Record format of the bitcode section
+mod section_utils;
+pub use section_utils::*;
+
 /// Filepath-related utility functions
 mod path_utils;
 pub use path_utils::*;
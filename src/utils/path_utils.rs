@@ -1,16 +1,29 @@
 //! Filepath-related utility functions
 
 use std::{
-    collections::hash_map::DefaultHasher,
-    hash::{Hash, Hasher},
-    path::{Path, PathBuf},
+    fmt,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
 };
 
+use sha2::{Digest, Sha256};
+
 use crate::error::Error;
 
+/// Derive filepaths of the object file and the bitcode file for the source
+///
+/// `object_filepath` is the object file written by the compiler, if any (i.e.,
+/// in compile-only mode); otherwise, a hidden object file next to the source
+/// file is derived. The bitcode file is hidden next to the object file.
+///
+/// The filenames of the bitcode file and the hidden object file include the
+/// artifact hash (see [`calculate_artifact_hash`]), so that compiling the same
+/// source file with different flags or into different outputs does not
+/// overwrite them.
 pub fn derive_object_and_bitcode_filepath<P>(
     src_filepath: P,
-    is_compile_only: bool,
+    object_filepath: Option<P>,
+    artifact_hash: u64,
 ) -> Result<(PathBuf, PathBuf), Error>
 where
     P: AsRef<Path>,
@@ -27,12 +40,6 @@ where
     let parent_dir = src_filepath
         .parent()
         .unwrap_or_else(|| panic!("Failed to obtain the parent directory: {:?}", src_filepath));
-    // With extension
-    let file_name = src_filepath
-        .file_name()
-        .unwrap_or_else(|| panic!("Failed to obtain the file name: {:?}", src_filepath))
-        .to_str()
-        .unwrap_or_else(|| panic!("Failed to convert OsStr to str: {:?}", src_filepath));
     // Without extension
     let file_stem = src_filepath
         .file_stem()
@@ -40,67 +47,434 @@ where
         .to_str()
         .unwrap_or_else(|| panic!("Failed to convert OsStr to str: {:?}", src_filepath));
 
-    let object_file_name = if is_compile_only {
-        // Compile only. We need to explicitly generate the object file
-        format!("{file_name}.o")
-    } else {
+    let object_filepath = match object_filepath {
+        // Compile only. The compiler has generated the object file
+        Some(object_filepath) => object_filepath.as_ref().to_path_buf(),
         // Hide the object file, as it is only for bitcode generation
-        format!(".{file_stem}.o")
+        None => parent_dir.join(format!(".{file_stem}_{artifact_hash:016x}.o")),
     };
-    // We always hide the bitcode file
-    let bitcode_file_name = format!(".{file_stem}.o.bc");
+    if !object_filepath.is_absolute() {
+        return Err(Error::InvalidArguments(format!(
+            "'object_filepath' must be absolute: {:?}",
+            object_filepath
+        )));
+    }
 
-    let object_filepath = parent_dir.join(object_file_name);
-    let bitcode_filepath = parent_dir.join(bitcode_file_name);
+    // We always hide the bitcode file
+    let bitcode_file_name = format!(".{file_stem}_{artifact_hash:016x}.o.bc");
+    let bitcode_filepath = object_filepath
+        .parent()
+        .unwrap_or(parent_dir)
+        .join(bitcode_file_name);
 
     Ok((object_filepath, bitcode_filepath))
 }
 
+/// Calculate a stable hash of the filepath
+///
+/// The hash is the first 8 bytes, read as a big-endian integer, of the SHA-256
+/// digest of the UTF-8 filepath. It does not depend on the platform, the Rust
+/// release or the process, so that names derived from it are reproducible.
 pub fn calculate_filepath_hash<P>(filepath: P) -> u64
 where
     P: AsRef<Path>,
 {
     let filepath = filepath.as_ref();
 
-    let mut hasher = DefaultHasher::new();
-    filepath.hash(&mut hasher);
-    hasher.finish()
+    let digest = Sha256::digest(filepath.to_string_lossy().as_bytes());
+    u64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+/// Calculate a stable hash identifying one compilation of a source file
+///
+/// The hash covers the source filepath, the output filepath (if any) and the
+/// effective compile flags, each terminated by a NUL byte, and is derived in
+/// the same way as [`calculate_filepath_hash`].
+pub fn calculate_artifact_hash<P, S>(
+    src_filepath: P,
+    output_filepath: Option<P>,
+    compile_args: &[S],
+) -> u64
+where
+    P: AsRef<Path>,
+    S: AsRef<str>,
+{
+    let mut hasher = Sha256::new();
+    hasher.update(src_filepath.as_ref().to_string_lossy().as_bytes());
+    hasher.update([0]);
+    if let Some(output_filepath) = output_filepath {
+        hasher.update(output_filepath.as_ref().to_string_lossy().as_bytes());
+    }
+    hasher.update([0]);
+    for compile_arg in compile_args {
+        hasher.update(compile_arg.as_ref().as_bytes());
+        hasher.update([0]);
+    }
+
+    let digest = hasher.finalize();
+    u64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+/// A path prefix rewrite rule in the form of `OLD=NEW`, similar to
+/// `-fdebug-prefix-map`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathPrefixMap {
+    old_prefix: PathBuf,
+    new_prefix: PathBuf,
+}
+
+impl PathPrefixMap {
+    pub fn new<P>(old_prefix: P, new_prefix: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Self {
+            old_prefix: old_prefix.as_ref().to_path_buf(),
+            new_prefix: new_prefix.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn old_prefix(&self) -> &PathBuf {
+        &self.old_prefix
+    }
+
+    pub fn new_prefix(&self) -> &PathBuf {
+        &self.new_prefix
+    }
+
+    /// Rewrite the given filepath, if it starts with the old prefix
+    pub fn apply<P>(&self, filepath: P) -> Option<PathBuf>
+    where
+        P: AsRef<Path>,
+    {
+        filepath
+            .as_ref()
+            .strip_prefix(&self.old_prefix)
+            .ok()
+            .map(|suffix| {
+                if suffix.as_os_str().is_empty() {
+                    self.new_prefix.clone()
+                } else {
+                    self.new_prefix.join(suffix)
+                }
+            })
+    }
+}
+
+impl FromStr for PathPrefixMap {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((old_prefix, new_prefix)) if !old_prefix.is_empty() => {
+                Ok(Self::new(old_prefix, new_prefix))
+            }
+            _ => Err(Error::InvalidArguments(format!(
+                "Path prefix map must be in the form of OLD=NEW: {:?}",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for PathPrefixMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}={}",
+            self.old_prefix.display(),
+            self.new_prefix.display()
+        )
+    }
+}
+
+/// Rewrite the filepath with the given prefix maps; the last matching map
+/// wins, as `-fdebug-prefix-map` does
+pub fn apply_path_prefix_maps<P>(filepath: P, path_prefix_maps: &[PathPrefixMap]) -> PathBuf
+where
+    P: AsRef<Path>,
+{
+    let filepath = filepath.as_ref();
+    path_prefix_maps
+        .iter()
+        .rev()
+        .find_map(|path_prefix_map| path_prefix_map.apply(filepath))
+        .unwrap_or_else(|| filepath.to_path_buf())
+}
+
+/// Split the compiler argument into the flag and the path, e.g., `-I` and
+/// `/old/include`, where the path follows the first `=` (e.g.,
+/// `--sysroot=/old`), or starts at the first `/` (e.g., `-I/old/include`)
+fn split_path_arg(arg: &str) -> (&str, &str) {
+    if !arg.starts_with('-') {
+        return ("", arg);
+    }
+
+    let path_start = arg
+        .find('=')
+        .map(|index| index + 1)
+        .or_else(|| arg.find('/'))
+        .unwrap_or(arg.len());
+    arg.split_at(path_start)
+}
+
+/// Rewrite the path in the compiler argument, e.g., `-I/old/include`, in the
+/// same way as [`apply_path_prefix_maps`]; the last matching map wins
+pub fn apply_path_prefix_maps_to_arg(arg: &str, path_prefix_maps: &[PathPrefixMap]) -> String {
+    let (flag, path) = split_path_arg(arg);
+    if path.is_empty() {
+        return arg.to_string();
+    }
+
+    path_prefix_maps
+        .iter()
+        .rev()
+        .find_map(|path_prefix_map| path_prefix_map.apply(path))
+        .map(|path| format!("{}{}", flag, path.display()))
+        .unwrap_or_else(|| arg.to_string())
+}
+
+/// Resolver of embedded bitcode filepaths, for build trees that have been
+/// moved after the build
+#[derive(Debug, Clone, Default)]
+pub struct BitcodePathResolver {
+    /// Prefix maps applied to every embedded filepath
+    path_prefix_maps: Vec<PathPrefixMap>,
+    /// Roots to search for bitcode files that do not exist after remapping
+    search_roots: Vec<PathBuf>,
+}
+
+impl BitcodePathResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a prefix map
+    #[must_use]
+    pub fn path_prefix_map(mut self, path_prefix_map: PathPrefixMap) -> Self {
+        self.path_prefix_maps.push(path_prefix_map);
+        self
+    }
+
+    /// Add a fallback search root
+    #[must_use]
+    pub fn search_root<P>(mut self, search_root: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.search_roots.push(search_root.as_ref().to_path_buf());
+        self
+    }
+
+    /// Resolve the embedded filepath
+    ///
+    /// The prefix maps are applied first. If the remapped file does not exist,
+    /// its trailing components are looked up under each search root, from the
+    /// longest to the shortest. The remapped filepath is returned if nothing
+    /// is found.
+    pub fn resolve<P>(&self, filepath: P) -> PathBuf
+    where
+        P: AsRef<Path>,
+    {
+        let filepath = apply_path_prefix_maps(filepath, &self.path_prefix_maps);
+        if filepath.exists() || self.search_roots.is_empty() {
+            return filepath;
+        }
+
+        let components: Vec<_> = filepath
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .collect();
+        for search_root in &self.search_roots {
+            for start in 0..components.len() {
+                let candidate: PathBuf = components[start..].iter().collect();
+                let candidate = search_root.join(candidate);
+                if candidate.exists() {
+                    log::debug!(
+                        "Resolve the bitcode filepath under the search root: {:?} -> {:?}",
+                        filepath,
+                        candidate
+                    );
+                    return candidate;
+                }
+            }
+        }
+
+        filepath
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::path::Path;
+    use std::{fs, path::Path};
 
     use super::*;
 
+    #[test]
+    fn test_calculate_artifact_hash() {
+        let src_filepath = Path::new("/tmp/foo.c");
+        let pic_object_filepath = Path::new("/tmp/.libs/foo.o");
+        let object_filepath = Path::new("/tmp/foo.o");
+
+        let pic_hash =
+            calculate_artifact_hash(src_filepath, Some(pic_object_filepath), &["-fPIC", "-O2"]);
+        let hash = calculate_artifact_hash(src_filepath, Some(object_filepath), &["-O2"]);
+        assert_ne!(pic_hash, hash);
+
+        // Different flags, same output
+        assert_ne!(
+            calculate_artifact_hash(src_filepath, Some(object_filepath), &["-DFOO"]),
+            calculate_artifact_hash(src_filepath, Some(object_filepath), &["-DBAR"]),
+        );
+
+        // Argument boundaries matter
+        assert_ne!(
+            calculate_artifact_hash(src_filepath, None, &["-DA", "B"]),
+            calculate_artifact_hash(src_filepath, None, &["-DAB"]),
+        );
+
+        // Stable
+        assert_eq!(
+            hash,
+            calculate_artifact_hash(src_filepath, Some(object_filepath), &["-O2"])
+        );
+    }
+
+    #[test]
+    fn test_apply_path_prefix_maps_to_arg() {
+        let path_prefix_maps: Vec<PathPrefixMap> =
+            vec!["/home/user/project=/build".parse().unwrap()];
+        assert_eq!(
+            apply_path_prefix_maps_to_arg("-I/home/user/project/include", &path_prefix_maps),
+            "-I/build/include"
+        );
+        assert_eq!(
+            apply_path_prefix_maps_to_arg("-O2", &path_prefix_maps),
+            "-O2"
+        );
+        assert_eq!(
+            apply_path_prefix_maps_to_arg(
+                "--sysroot=/home/user/project/sysroot",
+                &path_prefix_maps
+            ),
+            "--sysroot=/build/sysroot"
+        );
+        assert_eq!(
+            apply_path_prefix_maps_to_arg("/home/user/project", &path_prefix_maps),
+            "/build"
+        );
+
+        // Only prefixes at component boundaries are rewritten
+        let path_prefix_maps: Vec<PathPrefixMap> = vec!["/build=/x".parse().unwrap()];
+        for arg in [
+            "-I/builder/inc",
+            "-DDIR=\"/src/build\"",
+            "-I/src/build/inc",
+            "/builder/foo.c",
+        ] {
+            assert_eq!(apply_path_prefix_maps_to_arg(arg, &path_prefix_maps), arg);
+        }
+    }
+
+    #[test]
+    fn test_calculate_filepath_hash() {
+        // The hash must be stable across platforms and Rust releases
+        assert_eq!(
+            calculate_filepath_hash("/tmp/foo.c"),
+            u64::from_be_bytes(Sha256::digest(b"/tmp/foo.c")[..8].try_into().unwrap())
+        );
+        assert_eq!(calculate_filepath_hash(""), 0xe3b0c44298fc1c14);
+    }
+
+    #[test]
+    fn test_path_prefix_maps() {
+        let path_prefix_maps: Vec<PathPrefixMap> = ["/build=/old", "/build/src=/new/src"]
+            .iter()
+            .map(|x| x.parse().unwrap())
+            .collect();
+
+        // The last matching map wins
+        assert_eq!(
+            apply_path_prefix_maps("/build/src/foo.bc", &path_prefix_maps),
+            PathBuf::from("/new/src/foo.bc")
+        );
+        assert_eq!(
+            apply_path_prefix_maps("/build/bar.bc", &path_prefix_maps),
+            PathBuf::from("/old/bar.bc")
+        );
+        // Prefixes are matched by components
+        assert_eq!(
+            apply_path_prefix_maps("/builder/bar.bc", &path_prefix_maps),
+            PathBuf::from("/builder/bar.bc")
+        );
+
+        assert!("no-separator".parse::<PathPrefixMap>().is_err());
+        assert!("=/new".parse::<PathPrefixMap>().is_err());
+    }
+
+    #[test]
+    fn test_bitcode_path_resolver_search_root() {
+        let search_root = Path::new("/tmp/resolver_search_root");
+        let bitcode_filepath = search_root.join("src/foo.bc");
+        fs::create_dir_all(bitcode_filepath.parent().unwrap())
+            .expect("Failed to create the search root");
+        fs::write(&bitcode_filepath, b"").expect("Failed to write the bitcode file");
+
+        let resolver = BitcodePathResolver::new().search_root(search_root);
+        assert_eq!(
+            resolver.resolve("/nonexistent/build/src/foo.bc"),
+            bitcode_filepath
+        );
+        assert_eq!(
+            resolver.resolve("/nonexistent/build/src/bar.bc"),
+            PathBuf::from("/nonexistent/build/src/bar.bc")
+        );
+
+        // Clean
+        fs::remove_dir_all(search_root).expect("Failed to delete the search root");
+    }
+
     #[test]
     fn test_derive_object_and_bitcode_filepath() {
         let test_inputs = [
             (
                 Path::new("/tmp/foo.c"),
-                false,
-                (Path::new("/tmp/.foo.o"), Path::new("/tmp/.foo.o.bc")),
+                None,
+                (
+                    Path::new("/tmp/.foo_00000000deadbeef.o"),
+                    Path::new("/tmp/.foo_00000000deadbeef.o.bc"),
+                ),
             ),
             (
-                Path::new("/tmp/foo.c"),
-                true,
-                (Path::new("/tmp/foo.c.o"), Path::new("/tmp/.foo.o.bc")),
+                Path::new("/tmp/src/foo.c"),
+                Some(Path::new("/tmp/build/obj/foo.o")),
+                (
+                    Path::new("/tmp/build/obj/foo.o"),
+                    Path::new("/tmp/build/obj/.foo_00000000deadbeef.o.bc"),
+                ),
             ),
         ];
 
         assert!(test_inputs.iter().all(
             |&(
                 src_filepath,
-                is_compile_only,
+                object_filepath,
                 (expected_object_filepath, expected_bitcode_filepath),
             )| {
-                derive_object_and_bitcode_filepath(src_filepath, is_compile_only).is_ok_and(
-                    |(object_filepath, bitcode_filepath)| {
+                derive_object_and_bitcode_filepath(src_filepath, object_filepath, 0xdeadbeef)
+                    .is_ok_and(|(object_filepath, bitcode_filepath)| {
                         object_filepath == expected_object_filepath
                             && bitcode_filepath == expected_bitcode_filepath
-                    },
-                )
+                    })
             },
         ));
+
+        // Linking the same source file into different programs
+        let (object_filepath, bitcode_filepath) =
+            derive_object_and_bitcode_filepath(Path::new("/tmp/foo.c"), None, 1).unwrap();
+        let (other_object_filepath, other_bitcode_filepath) =
+            derive_object_and_bitcode_filepath(Path::new("/tmp/foo.c"), None, 2).unwrap();
+        assert_ne!(object_filepath, other_object_filepath);
+        assert_ne!(bitcode_filepath, other_bitcode_filepath);
     }
 }
@@ -0,0 +1,354 @@
+//! Response file (`@file`) utility functions
+
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    constants::{
+        MAX_RESPONSE_FILE_CREATION_ATTEMPTS, MAX_RESPONSE_FILE_DEPTH,
+        RESPONSE_FILE_ARGS_LENGTH_THRESHOLD,
+    },
+    error::Error,
+};
+
+/// Split the contents of a response file into arguments, following the GCC
+/// quoting rules: arguments are separated by whitespace, can be quoted by
+/// single or double quotes, and a backslash escapes the next character, both
+/// inside and outside of quotes
+pub fn split_response_file(contents: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut arg = String::new();
+    let mut is_in_arg = false;
+    let mut quote = None;
+
+    let mut chars = contents.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (_, '\\') => {
+                if let Some(c) = chars.next() {
+                    arg.push(c);
+                }
+                is_in_arg = true;
+            }
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => arg.push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                is_in_arg = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if is_in_arg {
+                    args.push(std::mem::take(&mut arg));
+                    is_in_arg = false;
+                }
+            }
+            (None, c) => {
+                arg.push(c);
+                is_in_arg = true;
+            }
+        }
+    }
+    if is_in_arg {
+        args.push(arg);
+    }
+
+    args
+}
+
+/// Quote the argument, so that [`split_response_file`] yields it unchanged
+pub fn quote_response_file_arg(arg: &str) -> String {
+    if arg.is_empty() {
+        return "\"\"".into();
+    }
+
+    let mut quoted = String::with_capacity(arg.len());
+    for c in arg.chars() {
+        if c.is_whitespace() || matches!(c, '\\' | '\'' | '"') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+
+    quoted
+}
+
+fn expand_response_files_recursively<S>(
+    args: &[S],
+    depth: usize,
+    expanded_args: &mut Vec<String>,
+) -> Result<(), Error>
+where
+    S: AsRef<str>,
+{
+    for arg in args {
+        let arg = arg.as_ref();
+        let Some(contents) = arg
+            .strip_prefix('@')
+            .filter(|filepath| !filepath.is_empty())
+            .and_then(|filepath| fs::read_to_string(filepath).ok())
+        else {
+            expanded_args.push(arg.to_string());
+            continue;
+        };
+
+        if depth >= MAX_RESPONSE_FILE_DEPTH {
+            return Err(Error::InvalidArguments(format!(
+                "Response files are nested too deeply: {}",
+                arg
+            )));
+        }
+        expand_response_files_recursively(
+            &split_response_file(&contents),
+            depth + 1,
+            expanded_args,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Replace every `@file` argument by the arguments in the file, recursively
+///
+/// As in GCC, relative filepaths are resolved against the current working
+/// directory, and the argument is kept as is if the file cannot be read.
+pub fn expand_response_files<S>(args: &[S]) -> Result<Vec<String>, Error>
+where
+    S: AsRef<str>,
+{
+    let mut expanded_args = vec![];
+    expand_response_files_recursively(args, 0, &mut expanded_args)?;
+
+    Ok(expanded_args)
+}
+
+/// A temporary response file, which is deleted when dropped
+#[derive(Debug)]
+pub struct ResponseFile {
+    filepath: PathBuf,
+}
+
+impl ResponseFile {
+    /// Write the arguments into a new temporary response file
+    ///
+    /// The file is always newly created, so that existing files (or symlinks
+    /// planted in a shared temporary directory) are never written through.
+    pub fn try_new<S>(args: &[S]) -> Result<Self, Error>
+    where
+        S: AsRef<str>,
+    {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let filepaths = (0..MAX_RESPONSE_FILE_CREATION_ATTEMPTS).map(|_| {
+            env::temp_dir().join(format!(
+                "rllvm-{}-{}.rsp",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ))
+        });
+
+        Self::try_new_at_any(args, filepaths)
+    }
+
+    /// Write the arguments into the first of the given filepaths that does not
+    /// exist yet
+    fn try_new_at_any<S, I>(args: &[S], filepaths: I) -> Result<Self, Error>
+    where
+        S: AsRef<str>,
+        I: IntoIterator<Item = PathBuf>,
+    {
+        let contents: Vec<String> = args
+            .iter()
+            .map(|arg| quote_response_file_arg(arg.as_ref()))
+            .collect();
+
+        for filepath in filepaths {
+            let mut options = OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.mode(0o600);
+            }
+            let mut file = match options.open(&filepath) {
+                Ok(file) => file,
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err.into()),
+            };
+
+            // Delete the file if the write fails
+            let response_file = Self { filepath };
+            file.write_all(contents.join("\n").as_bytes())?;
+
+            return Ok(response_file);
+        }
+
+        Err(Error::Io(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "Failed to create a unique response file",
+        )))
+    }
+
+    /// Write the arguments into a temporary response file, if they are too
+    /// long for a command line
+    pub fn try_new_if_too_long<S>(args: &[S]) -> Result<Option<Self>, Error>
+    where
+        S: AsRef<str>,
+    {
+        let args_length: usize = args.iter().map(|arg| arg.as_ref().len() + 1).sum();
+        if args_length <= RESPONSE_FILE_ARGS_LENGTH_THRESHOLD {
+            return Ok(None);
+        }
+
+        Self::try_new(args).map(Some)
+    }
+
+    pub fn filepath(&self) -> &Path {
+        &self.filepath
+    }
+
+    /// Obtain the argument that refers to the response file, i.e., `@file`
+    pub fn arg(&self) -> String {
+        format!("@{}", self.filepath.display())
+    }
+}
+
+impl Drop for ResponseFile {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.filepath) {
+            log::warn!(
+                "Failed to delete the response file: {:?}, err={}",
+                self.filepath,
+                err
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_response_file() {
+        let args = split_response_file(
+            "-c foo.c\n-o 'out dir/foo.o' \"-DNAME=\\\"a b\\\"\" a\\ b '' -I\\\\inc\n",
+        );
+        assert_eq!(
+            args,
+            vec![
+                "-c",
+                "foo.c",
+                "-o",
+                "out dir/foo.o",
+                "-DNAME=\"a b\"",
+                "a b",
+                "",
+                "-I\\inc"
+            ]
+        );
+
+        for arg in ["", "a b", "-DNAME=\"a b\"", "it's", "C:\\path", "tab\there"] {
+            assert_eq!(
+                split_response_file(&quote_response_file_arg(arg)),
+                vec![arg.to_string()]
+            );
+        }
+    }
+
+    #[test]
+    fn test_expand_response_files() {
+        let dir = env::temp_dir().join("rllvm_test_expand_response_files");
+        fs::create_dir_all(&dir).expect("Failed to create the directory");
+        let inner_filepath = dir.join("inner.rsp");
+        let outer_filepath = dir.join("outer.rsp");
+        fs::write(&inner_filepath, "a.o 'b c.o'").expect("Failed to write the response file");
+        fs::write(
+            &outer_filepath,
+            format!("-o foo @{}\n-lm", inner_filepath.display()),
+        )
+        .expect("Failed to write the response file");
+
+        let outer_arg = format!("@{}", outer_filepath.display());
+        let args = expand_response_files(&["-O2", &outer_arg, "@nonexistent.rsp", "@"])
+            .expect("Failed to expand response files");
+        assert_eq!(
+            args,
+            vec![
+                "-O2",
+                "-o",
+                "foo",
+                "a.o",
+                "b c.o",
+                "-lm",
+                "@nonexistent.rsp",
+                "@"
+            ]
+        );
+
+        // Recursive response files
+        fs::write(&inner_filepath, format!("@{}", inner_filepath.display()))
+            .expect("Failed to write the response file");
+        assert!(expand_response_files(&[&outer_arg]).is_err());
+
+        // Written response files are read back
+        let long_arg = "x".repeat(RESPONSE_FILE_ARGS_LENGTH_THRESHOLD);
+        let args = [long_arg.as_str(), "a b", "-DNAME=\"x\""];
+        let response_file = ResponseFile::try_new_if_too_long(&args)
+            .expect("Failed to write the response file")
+            .expect("Arguments are long enough");
+        assert_eq!(expand_response_files(&[response_file.arg()]).unwrap(), args);
+        let response_filepath = response_file.filepath().to_path_buf();
+        drop(response_file);
+        assert!(!response_filepath.exists());
+        assert!(
+            ResponseFile::try_new_if_too_long(&["-c", "foo.c"])
+                .unwrap()
+                .is_none()
+        );
+
+        // Clean
+        fs::remove_dir_all(&dir).expect("Failed to delete the directory");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_response_file_does_not_follow_symlinks() {
+        let dirpath = env::temp_dir().join("rllvm_test_response_file_symlinks");
+        let _ = fs::remove_dir_all(&dirpath);
+        fs::create_dir_all(&dirpath).expect("Failed to create the directory");
+        let target_filepath = dirpath.join("target");
+        fs::write(&target_filepath, "unchanged").expect("Failed to write the target file");
+
+        // Plant symlinks at the first candidate filepaths
+        let filepaths: Vec<_> = ["a.rsp", "b.rsp", "c.rsp"]
+            .iter()
+            .map(|filename| dirpath.join(filename))
+            .collect();
+        for filepath in &filepaths[..2] {
+            std::os::unix::fs::symlink(&target_filepath, filepath)
+                .expect("Failed to create the symlink");
+        }
+
+        let response_file =
+            ResponseFile::try_new_at_any(&["-o", "foo"], filepaths.clone()).unwrap();
+        assert_eq!(response_file.filepath(), filepaths[2]);
+        assert_eq!(fs::read_to_string(&target_filepath).unwrap(), "unchanged");
+        assert_eq!(
+            expand_response_files(&[response_file.arg()]).unwrap(),
+            ["-o", "foo"]
+        );
+
+        // Every candidate exists
+        assert!(ResponseFile::try_new_at_any(&["-c"], filepaths[..2].to_vec()).is_err());
+        assert_eq!(fs::read_to_string(&target_filepath).unwrap(), "unchanged");
+
+        // Clean
+        drop(response_file);
+        fs::remove_dir_all(&dirpath).expect("Failed to delete the directory");
+    }
+}
@@ -0,0 +1,441 @@
+//! Record format of the bitcode section
+//!
+//! Each call to [`embed_bitcode_record_to_object_file`] appends one
+//! self-delimiting record to the bitcode section, so that records survive the
+//! concatenation performed by the linker:
+//!
+//! ```text
+//! record  := magic version length field*
+//! magic   := b"RLLVMBC\0"
+//! version := u16 (little endian)
+//! length  := u32 (little endian), the total byte length of all fields
+//! field   := tag: u8, length: u32 (little endian), value: [u8; length]
+//! ```
+//!
+//! A record carries the filepath of the bitcode file, and optionally its digest
+//! and its contents (for self-contained objects). If the bitcode could not be
+//! generated, the record carries the filepath of the source file and the reason
+//! instead. Fields with unknown tags are skipped. Bytes outside of records are treated
+//! as the legacy format, i.e., newline-separated bitcode filepaths.
+//!
+//! [`embed_bitcode_record_to_object_file`]: crate::utils::embed_bitcode_record_to_object_file
+
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    str,
+};
+
+use flate2::{Compression, read::ZlibDecoder, write::ZlibEncoder};
+
+use crate::{
+    constants::{BITCODE_SECTION_MAGIC, BITCODE_SECTION_VERSION},
+    error::Error,
+};
+
+/// Size of the record header: magic, version and length
+const RECORD_HEADER_SIZE: usize = BITCODE_SECTION_MAGIC.len() + 2 + 4;
+
+/// Size of the field header: tag and length
+const FIELD_HEADER_SIZE: usize = 1 + 4;
+
+/// Field tags of a bitcode section record
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FieldTag {
+    /// The absolute filepath of the bitcode file
+    Filepath = 1,
+    /// The SHA-256 digest of the bitcode file
+    Digest = 2,
+    /// The contents of the bitcode file
+    Contents = 3,
+    /// The zlib-compressed contents of the bitcode file
+    CompressedContents = 4,
+    /// The reason why the bitcode of the source file is missing
+    MissingReason = 5,
+}
+
+/// The SHA-256 digest of a bitcode file
+pub type BitcodeDigest = [u8; 32];
+
+/// A record of the bitcode section, describing one bitcode module
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BitcodeSectionRecord {
+    filepath: PathBuf,
+    digest: Option<BitcodeDigest>,
+    /// Contents of the bitcode file, as stored in the section
+    contents: Option<Vec<u8>>,
+    is_compressed: bool,
+    /// Why the bitcode is missing, in which case the filepath is the source
+    /// file's
+    missing_reason: Option<String>,
+}
+
+impl BitcodeSectionRecord {
+    pub fn new<P>(filepath: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Self {
+            filepath: filepath.as_ref().to_path_buf(),
+            digest: None,
+            contents: None,
+            is_compressed: false,
+            missing_reason: None,
+        }
+    }
+
+    /// Create the record of a source file whose bitcode could not be generated
+    pub fn missing<P>(src_filepath: P, reason: &str) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Self {
+            missing_reason: Some(reason.to_string()),
+            ..Self::new(src_filepath)
+        }
+    }
+
+    /// Attach the contents of the bitcode file, optionally compressed
+    pub fn with_contents(mut self, contents: &[u8], is_compressed: bool) -> Result<Self, Error> {
+        let contents = if is_compressed {
+            let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+            encoder.write_all(contents)?;
+            encoder.finish()?
+        } else {
+            contents.to_vec()
+        };
+        self.contents = Some(contents);
+        self.is_compressed = is_compressed;
+        Ok(self)
+    }
+
+    /// Attach the digest of the bitcode file
+    #[must_use]
+    pub fn with_digest(mut self, digest: BitcodeDigest) -> Self {
+        self.digest = Some(digest);
+        self
+    }
+
+    /// Replace the filepath of the bitcode file, e.g., after remapping
+    #[must_use]
+    pub fn with_filepath<P>(mut self, filepath: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.filepath = filepath.as_ref().to_path_buf();
+        self
+    }
+
+    pub fn filepath(&self) -> &PathBuf {
+        &self.filepath
+    }
+
+    /// The digest of the bitcode file at embedding time; records written by
+    /// older versions do not have one
+    pub fn digest(&self) -> Option<&BitcodeDigest> {
+        self.digest.as_ref()
+    }
+
+    /// Why the bitcode of the source file is missing, if it is
+    pub fn missing_reason(&self) -> Option<&str> {
+        self.missing_reason.as_deref()
+    }
+
+    /// Returns `true` if the record carries the bitcode itself
+    pub fn has_contents(&self) -> bool {
+        self.contents.is_some()
+    }
+
+    /// The (decompressed) contents of the bitcode file, if embedded
+    pub fn contents(&self) -> Result<Option<Vec<u8>>, Error> {
+        let Some(contents) = &self.contents else {
+            return Ok(None);
+        };
+        if !self.is_compressed {
+            return Ok(Some(contents.clone()));
+        }
+
+        let mut decompressed = vec![];
+        ZlibDecoder::new(contents.as_slice()).read_to_end(&mut decompressed)?;
+        Ok(Some(decompressed))
+    }
+
+    /// Serialize the record, including its header
+    ///
+    /// Fails if a field or the record is too large for its 32-bit length.
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        let mut fields = vec![];
+        encode_field(
+            &mut fields,
+            FieldTag::Filepath,
+            self.filepath.to_string_lossy().as_bytes(),
+        )?;
+        if let Some(digest) = &self.digest {
+            encode_field(&mut fields, FieldTag::Digest, digest)?;
+        }
+        if let Some(contents) = &self.contents {
+            let tag = if self.is_compressed {
+                FieldTag::CompressedContents
+            } else {
+                FieldTag::Contents
+            };
+            encode_field(&mut fields, tag, contents)?;
+        }
+        if let Some(missing_reason) = &self.missing_reason {
+            encode_field(
+                &mut fields,
+                FieldTag::MissingReason,
+                missing_reason.as_bytes(),
+            )?;
+        }
+
+        let mut data = Vec::with_capacity(RECORD_HEADER_SIZE + fields.len());
+        data.extend_from_slice(BITCODE_SECTION_MAGIC);
+        data.extend_from_slice(&BITCODE_SECTION_VERSION.to_le_bytes());
+        data.extend_from_slice(&encode_length(fields.len())?);
+        data.extend(fields);
+
+        Ok(data)
+    }
+
+    fn decode_fields(fields: &[u8]) -> Result<Self, Error> {
+        let mut filepath = None;
+        let mut digest = None;
+        let mut contents = None;
+        let mut is_compressed = false;
+        let mut missing_reason = None;
+
+        let mut offset = 0;
+        while offset < fields.len() {
+            if fields.len() - offset < FIELD_HEADER_SIZE {
+                return Err(Error::MalformedSection(format!(
+                    "Truncated field header: offset={}",
+                    offset
+                )));
+            }
+            let tag = fields[offset];
+            let length = read_u32(&fields[offset + 1..]) as usize;
+            let value_start = offset + FIELD_HEADER_SIZE;
+            let value_end = value_start + length;
+            if value_end > fields.len() {
+                return Err(Error::MalformedSection(format!(
+                    "Truncated field value: tag={}, length={}",
+                    tag, length
+                )));
+            }
+            let value = &fields[value_start..value_end];
+
+            if tag == FieldTag::Filepath as u8 {
+                filepath = Some(PathBuf::from(str::from_utf8(value)?));
+            } else if tag == FieldTag::Digest as u8 {
+                digest = Some(BitcodeDigest::try_from(value).map_err(|_| {
+                    Error::MalformedSection(format!("Invalid digest length: length={}", length))
+                })?);
+            } else if tag == FieldTag::Contents as u8 {
+                contents = Some(value.to_vec());
+                is_compressed = false;
+            } else if tag == FieldTag::CompressedContents as u8 {
+                contents = Some(value.to_vec());
+                is_compressed = true;
+            } else if tag == FieldTag::MissingReason as u8 {
+                missing_reason = Some(str::from_utf8(value)?.to_string());
+            } else {
+                log::debug!("Skip unknown field of the bitcode section: tag={}", tag);
+            }
+
+            offset = value_end;
+        }
+
+        let filepath = filepath.ok_or_else(|| {
+            Error::MalformedSection("The record does not contain a filepath".to_string())
+        })?;
+
+        Ok(Self {
+            filepath,
+            digest,
+            contents,
+            is_compressed,
+            missing_reason,
+        })
+    }
+}
+
+fn encode_field(data: &mut Vec<u8>, tag: FieldTag, value: &[u8]) -> Result<(), Error> {
+    data.push(tag as u8);
+    data.extend_from_slice(&encode_length(value.len())?);
+    data.extend_from_slice(value);
+
+    Ok(())
+}
+
+fn encode_length(length: usize) -> Result<[u8; 4], Error> {
+    let length = u32::try_from(length).map_err(|_| {
+        Error::InvalidArguments(format!(
+            "Too large for the bitcode section: length={}",
+            length
+        ))
+    })?;
+
+    Ok(length.to_le_bytes())
+}
+
+fn read_u16(data: &[u8]) -> u16 {
+    u16::from_le_bytes([data[0], data[1]])
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+}
+
+/// Find the offset of the next record header, if any
+fn find_record_start(data: &[u8]) -> Option<usize> {
+    data.windows(BITCODE_SECTION_MAGIC.len())
+        .position(|window| window == BITCODE_SECTION_MAGIC)
+}
+
+/// Parse newline-separated bitcode filepaths written by older versions
+fn decode_legacy_records(data: &[u8]) -> Result<Vec<BitcodeSectionRecord>, Error> {
+    Ok(str::from_utf8(data)?
+        .split(['\n', '\0'])
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(BitcodeSectionRecord::new)
+        .collect())
+}
+
+/// Decode the (possibly concatenated) contents of the bitcode section
+pub fn decode_bitcode_section(data: &[u8]) -> Result<Vec<BitcodeSectionRecord>, Error> {
+    let mut records = vec![];
+
+    let mut offset = 0;
+    while offset < data.len() {
+        let remaining = &data[offset..];
+        if !remaining.starts_with(BITCODE_SECTION_MAGIC) {
+            // Legacy contents, up to the next record
+            let legacy_end = find_record_start(remaining).unwrap_or(remaining.len());
+            records.extend(decode_legacy_records(&remaining[..legacy_end])?);
+            offset += legacy_end;
+            continue;
+        }
+
+        if remaining.len() < RECORD_HEADER_SIZE {
+            return Err(Error::MalformedSection(format!(
+                "Truncated record header: offset={}",
+                offset
+            )));
+        }
+        let version = read_u16(&remaining[BITCODE_SECTION_MAGIC.len()..]);
+        if version > BITCODE_SECTION_VERSION {
+            return Err(Error::MalformedSection(format!(
+                "Unsupported record version: version={}, supported={}",
+                version, BITCODE_SECTION_VERSION
+            )));
+        }
+        let length = read_u32(&remaining[BITCODE_SECTION_MAGIC.len() + 2..]) as usize;
+        let record_end = RECORD_HEADER_SIZE + length;
+        if record_end > remaining.len() {
+            return Err(Error::MalformedSection(format!(
+                "Truncated record: offset={}, length={}",
+                offset, length
+            )));
+        }
+
+        records.push(BitcodeSectionRecord::decode_fields(
+            &remaining[RECORD_HEADER_SIZE..record_end],
+        )?);
+        offset += record_end;
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_length() {
+        assert_eq!(encode_length(0x0102).unwrap(), [0x02, 0x01, 0, 0]);
+        assert!(encode_length(u32::MAX as usize).is_ok());
+        #[cfg(target_pointer_width = "64")]
+        assert!(encode_length(u32::MAX as usize + 1).is_err());
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        let record = BitcodeSectionRecord::new("/tmp/foo.bc");
+        let records = decode_bitcode_section(&record.encode().unwrap()).expect("Failed to decode");
+        assert_eq!(records, vec![record]);
+    }
+
+    #[test]
+    fn test_record_with_digest_round_trip() {
+        let record = BitcodeSectionRecord::new("/tmp/foo.bc").with_digest([0xab; 32]);
+        let records = decode_bitcode_section(&record.encode().unwrap()).expect("Failed to decode");
+        assert_eq!(records, vec![record]);
+        assert_eq!(records[0].digest(), Some(&[0xab; 32]));
+    }
+
+    #[test]
+    fn test_record_with_contents_round_trip() {
+        let contents = b"BC\xc0\xde bitcode bitcode bitcode bitcode".to_vec();
+        for is_compressed in [false, true] {
+            let record = BitcodeSectionRecord::new("/tmp/foo.bc")
+                .with_contents(&contents, is_compressed)
+                .expect("Failed to attach contents");
+            let records =
+                decode_bitcode_section(&record.encode().unwrap()).expect("Failed to decode");
+            assert_eq!(records, vec![record]);
+            assert_eq!(records[0].contents().unwrap(), Some(contents.clone()));
+        }
+    }
+
+    #[test]
+    fn test_missing_record_round_trip() {
+        let record = BitcodeSectionRecord::missing("/tmp/foo.c", "exit status: 1");
+        let records = decode_bitcode_section(&record.encode().unwrap()).expect("Failed to decode");
+        assert_eq!(records, vec![record]);
+        assert_eq!(records[0].missing_reason(), Some("exit status: 1"));
+        assert_eq!(records[0].filepath(), &PathBuf::from("/tmp/foo.c"));
+    }
+
+    #[test]
+    fn test_concatenated_records() {
+        // Simulate the linker concatenating sections of several objects, mixing
+        // the legacy format, alignment padding and the record format
+        let mut data = vec![];
+        data.extend(BitcodeSectionRecord::new("/tmp/foo.bc").encode().unwrap());
+        data.extend(BitcodeSectionRecord::new("/tmp/bar.bc").encode().unwrap());
+        data.extend_from_slice(b"/tmp/legacy_1.bc\n/tmp/legacy_2.bc\n");
+        data.extend(
+            BitcodeSectionRecord::new("/tmp/with\nnewline.bc")
+                .encode()
+                .unwrap(),
+        );
+        data.extend_from_slice(b"/tmp/legacy_3.bc\0\0\0");
+        data.extend(BitcodeSectionRecord::new("/tmp/baz.bc").encode().unwrap());
+
+        let filepaths: Vec<_> = decode_bitcode_section(&data)
+            .expect("Failed to decode")
+            .into_iter()
+            .map(|record| record.filepath().clone())
+            .collect();
+        let expected_filepaths = vec![
+            PathBuf::from("/tmp/foo.bc"),
+            PathBuf::from("/tmp/bar.bc"),
+            PathBuf::from("/tmp/legacy_1.bc"),
+            PathBuf::from("/tmp/legacy_2.bc"),
+            PathBuf::from("/tmp/with\nnewline.bc"),
+            PathBuf::from("/tmp/legacy_3.bc"),
+            PathBuf::from("/tmp/baz.bc"),
+        ];
+        assert_eq!(filepaths, expected_filepaths);
+    }
+
+    #[test]
+    fn test_truncated_record() {
+        let data = BitcodeSectionRecord::new("/tmp/foo.bc").encode().unwrap();
+        assert!(decode_bitcode_section(&data[..data.len() - 1]).is_err());
+    }
+}